@@ -0,0 +1,108 @@
+use chrono::{Datelike, Local, Timelike};
+use std::io::{Cursor, Read, Write};
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("Archive(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Archive(Zip({0:?}))")]
+    Zip(#[from] zip::result::ZipError),
+}
+type Result<T> = std::result::Result<T, ArchiveError>;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// every file in an archive, as (name, data)
+pub fn read(format: ArchiveFormat, data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut entries = vec![];
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if file.is_dir() {
+                    continue;
+                }
+                let mut buf = vec![];
+                file.read_to_end(&mut buf)?;
+                entries.push((file.name().to_string(), buf));
+            }
+        }
+        ArchiveFormat::TarZst => {
+            let tar = zstd::decode_all(data)?;
+            let mut archive = tar::Archive::new(Cursor::new(tar));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().to_string();
+                let mut buf = vec![];
+                entry.read_to_end(&mut buf)?;
+                entries.push((name, buf));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// an archive of `entries`, as (name, data), in that order
+pub fn write(format: ArchiveFormat, entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let now = Local::now();
+
+    match format {
+        ArchiveFormat::Zip => {
+            let modified = zip::DateTime::from_date_and_time(
+                now.year() as u16,
+                now.month() as u8,
+                now.day() as u8,
+                now.hour() as u8,
+                now.minute() as u8,
+                now.second() as u8,
+            )
+            .unwrap_or_default();
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .last_modified_time(modified);
+
+            let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+            for (name, data) in entries {
+                zip.start_file(name.as_str(), options)?;
+                zip.write_all(data)?;
+            }
+            Ok(zip.finish()?.into_inner())
+        }
+        ArchiveFormat::TarZst => {
+            let mut tar = tar::Builder::new(vec![]);
+            for (name, data) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(now.timestamp().max(0) as u64);
+                header.set_cksum();
+                tar.append_data(&mut header, name, data.as_slice())?;
+            }
+            let tar = tar.into_inner()?;
+
+            Ok(zstd::encode_all(tar.as_slice(), 0)?)
+        }
+    }
+}
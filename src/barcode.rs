@@ -0,0 +1,284 @@
+use crate::{
+    common,
+    config::{Srfax, CONFIG},
+    convert::{self, ConvertError},
+    response::DownloadFormat,
+};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Error)]
+pub enum BarcodeError {
+    #[error("Barcode(Convert({0:?}))")]
+    Convert(#[from] ConvertError),
+
+    #[error("Barcode(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Barcode(zbarimg not found at {0:?})")]
+    NotInstalled(String),
+
+    #[error("Barcode(zbarimg failed: {0})")]
+    Zbar(String),
+}
+type Result<T> = std::result::Result<T, BarcodeError>;
+
+/// zbarimg's exit code when it ran fine but found nothing
+const ZBAR_NOTHING_FOUND: i32 = 4;
+
+/// one barcode found on a fax
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Barcode {
+    /// as zbar names it, e.g. `QR-Code`, `CODE-128` or `EAN-13`
+    #[serde(rename = "type")]
+    pub symbology: String,
+    pub data: String,
+    /// starting at 1
+    pub page: u32,
+}
+
+/// a directory for faxes carrying a barcode that starts with `prefix`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BarcodeRoute {
+    pub prefix: String,
+    /// used instead of the account's `dir_template`
+    pub dir_template: String,
+}
+
+/// the first route, in config order, with a matching barcode
+pub fn route<'a>(
+    srfax: &'a Srfax,
+    barcodes: &'a [Barcode],
+) -> Option<(&'a BarcodeRoute, &'a Barcode)> {
+    srfax.barcode_routes.iter().find_map(|route| {
+        barcodes
+            .iter()
+            .find(|barcode| barcode.data.starts_with(&route.prefix))
+            .map(|barcode| (route, barcode))
+    })
+}
+
+/// decodes the barcodes on the first `barcode_pages` pages of a downloaded
+/// fax, from its tiff if it was downloaded as one
+pub fn scan(srfax: &Srfax, files: &[(DownloadFormat, Vec<u8>)]) -> Result<Vec<Barcode>> {
+    let (format, data) = files
+        .iter()
+        .find(|(format, _)| *format == DownloadFormat::TIF)
+        .unwrap_or(&files[0]);
+    let image = convert::square_tiff(data, format, srfax.barcode_pages.map(|n| n as usize))?;
+
+    let dir = common::private_temp_dir("srfax-barcode")?;
+    let result = zbarimg(&dir, &image);
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("could not remove {:?}! {:?}", dir, e);
+    }
+    result
+}
+
+fn zbarimg(dir: &Path, image: &[u8]) -> Result<Vec<Barcode>> {
+    let input = dir.join("fax.tif");
+    fs::write(&input, image)?;
+
+    let output = std::process::Command::new(&CONFIG.zbarimg)
+        .args(["--xml", "-q"])
+        .arg(&input)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => BarcodeError::NotInstalled(CONFIG.zbarimg.clone()),
+            _ => e.into(),
+        })?;
+    match output.status.code() {
+        Some(0) => Ok(parse_xml(&String::from_utf8_lossy(&output.stdout))),
+        Some(ZBAR_NOTHING_FOUND) => Ok(vec![]),
+        _ => Err(BarcodeError::Zbar(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// reads zbarimg's xml, which has an `<index num='N'>` for every page of the
+/// tiff with its `<symbol>`s
+fn parse_xml(xml: &str) -> Vec<Barcode> {
+    let mut barcodes = vec![];
+    let mut page = 1;
+    let mut rest = xml;
+
+    while let Some(i) = rest.find('<') {
+        rest = &rest[i..];
+        if rest.starts_with("<index") {
+            if let Some(num) = attribute(rest, "num").and_then(|n| n.parse::<u32>().ok()) {
+                page = num + 1;
+            }
+        } else if rest.starts_with("<symbol") {
+            // cut off output, the symbol may be missing part of its data
+            let end = match rest.find("</symbol>") {
+                Some(end) => end,
+                None => break,
+            };
+            let symbol = &rest[..end];
+
+            let barcode = Barcode {
+                symbology: attribute(symbol, "type").unwrap_or_default().to_string(),
+                data: symbol_data(symbol),
+                page,
+            };
+            if !barcode.data.is_empty() && !barcodes.contains(&barcode) {
+                barcodes.push(barcode);
+            }
+            rest = &rest[end..];
+        }
+        rest = &rest[1..];
+    }
+
+    barcodes
+}
+
+/// the value of an attribute of the tag `tag` starts with
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    for quote in ['\'', '"'] {
+        let needle = format!(" {}={}", name, quote);
+        if let Some(start) = tag.find(&needle).map(|i| i + needle.len()) {
+            let len = tag[start..].find(quote)?;
+            return Some(&tag[start..start + len]);
+        }
+    }
+    None
+}
+
+/// the text in `<data>`, zbar splits it into several cdata sections when it
+/// contains `]]>` and base64 encodes binary data
+fn symbol_data(symbol: &str) -> String {
+    let data = match symbol.find("<data") {
+        Some(start) => &symbol[start..],
+        None => return String::new(),
+    };
+
+    let mut text = String::new();
+    let mut rest = data;
+    while let Some(start) = rest.find("<![CDATA[") {
+        rest = &rest[start + 9..];
+        let end = rest.find("]]>").unwrap_or(rest.len());
+        text.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    if attribute(data, "format") == Some("base64") {
+        use base64::prelude::*;
+        return match BASE64_STANDARD.decode(text.trim()) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => String::new(),
+        };
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn barcode(symbology: &str, data: &str, page: u32) -> Barcode {
+        Barcode {
+            symbology: symbology.to_string(),
+            data: data.to_string(),
+            page,
+        }
+    }
+
+    #[test]
+    fn parses_pages() {
+        let xml = "<barcodes xmlns='http://zbar.sourceforge.net/2008/barcode'>
+<source href='/tmp/srfax-barcode-1-0/fax.tif'>
+<index num='0'>
+<symbol type='CODE-128' quality='1' orientation='UP'><data><![CDATA[PT12345]]></data></symbol>
+</index>
+<index num='2'>
+<symbol type=\"QR-Code\" quality=\"1\"><data><![CDATA[CASE-9]]></data></symbol>
+<symbol type='EAN-13' quality='2'><data><![CDATA[4006381333931]]></data></symbol>
+<symbol type='CODE-128' quality='1'><data><![CDATA[PT12345]]></data></symbol>
+</index>
+</source>
+</barcodes>";
+
+        assert_eq!(
+            parse_xml(xml),
+            [
+                barcode("CODE-128", "PT12345", 1),
+                barcode("QR-Code", "CASE-9", 3),
+                barcode("EAN-13", "4006381333931", 3),
+                barcode("CODE-128", "PT12345", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_split_cdata() {
+        // zbar ends the cdata section at `]]>` in the data and starts another
+        let xml = "<index num='0'><symbol type='QR-Code' quality='1'>\
+            <data><![CDATA[a]]]]><![CDATA[>b <c> & d]]></data></symbol></index>";
+
+        assert_eq!(parse_xml(xml), [barcode("QR-Code", "a]]>b <c> & d", 1)]);
+    }
+
+    #[test]
+    fn parses_base64() {
+        let xml = "<index num='1'><symbol type='QR-Code' quality='1'>\
+            <data format='base64' length='5'><![CDATA[Q0FTRTk=\n]]></data></symbol></index>";
+
+        assert_eq!(parse_xml(xml), [barcode("QR-Code", "CASE9", 2)]);
+    }
+
+    #[test]
+    fn parses_truncated() {
+        let xml = "<index num='0'><symbol type='CODE-128' quality='1'>\
+            <data><![CDATA[PT12345]]></data></symbol>\
+            <symbol type='QR-Code' quality='1'><data><![CDATA[CAS";
+
+        assert_eq!(parse_xml(xml), [barcode("CODE-128", "PT12345", 1)]);
+        assert!(parse_xml("<symbol").is_empty());
+        assert!(parse_xml("<index num='0'><symbol type='CODE-128'>").is_empty());
+        assert!(parse_xml("<index num='").is_empty());
+    }
+
+    #[test]
+    fn skips_empty_and_repeated() {
+        let xml = "<index num='0'>\
+            <symbol type='CODE-39' quality='1'><data><![CDATA[]]></data></symbol>\
+            <symbol type='CODE-39' quality='1'><data><![CDATA[A1]]></data></symbol>\
+            <symbol type='CODE-39' quality='1'><data><![CDATA[A1]]></data></symbol>\
+            </index>";
+
+        assert_eq!(parse_xml(xml), [barcode("CODE-39", "A1", 1)]);
+        assert!(parse_xml("").is_empty());
+    }
+
+    #[test]
+    fn routes_by_first_matching_route() {
+        let srfax: Srfax = serde_json::from_str(
+            r#"{
+                "name": "clinic",
+                "access_id": "1",
+                "access_pwd": "",
+                "file_dir": "faxes",
+                "download_fmt": "PDF",
+                "delete_after": false,
+                "barcode_routes": [
+                    {"prefix": "CASE", "dir_template": "cases/{barcode}"},
+                    {"prefix": "PT", "dir_template": "patients/{barcode}"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let barcodes = [
+            barcode("EAN-13", "4006381333931", 1),
+            barcode("CODE-128", "PT12345", 1),
+            barcode("QR-Code", "CASE9", 2),
+        ];
+
+        let (matched, found) = route(&srfax, &barcodes).unwrap();
+        assert_eq!(matched.prefix, "CASE");
+        assert_eq!(found.data, "CASE9");
+        assert!(route(&srfax, &barcodes[..2]).is_some_and(|(matched, _)| matched.prefix == "PT"));
+        assert!(route(&srfax, &barcodes[..1]).is_none());
+    }
+}
@@ -1,5 +1,10 @@
-use crate::{common::winservice, config, Result};
-use clap::{Arg, ArgMatches, Command};
+use crate::srfax_service::{self, RedownloadTarget};
+use crate::{
+    common::winservice, config, control, encryption, history, ocr, quarantine, reconcile,
+    retention, verify, Result,
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
 
 pub fn handle_cla() -> Result<()> {
     let matches = gen_clap().get_matches();
@@ -17,8 +22,67 @@ fn gen_clap() -> Command {
                 .num_args(0)
                 .help("write the default config"),
         )
-        .subcommand(Command::new("run").about("run program"));
+        .subcommand(
+            Command::new("run")
+                .about("run program")
+                .arg(
+                    Arg::new("once")
+                        .long("once")
+                        .action(ArgAction::SetTrue)
+                        .help("poll every account once, print a summary and exit"),
+                )
+                .arg(
+                    Arg::new("account")
+                        .long("account")
+                        .num_args(1)
+                        .action(ArgAction::Append)
+                        .requires("once")
+                        .help("only poll the named account, can be repeated"),
+                ),
+        )
+        .subcommand(
+            Command::new("redownload")
+                .about("download faxes again, keeping the previous copy as a numbered version")
+                .arg(
+                    Arg::new("account")
+                        .long("account")
+                        .required(true)
+                        .help("the srfax account name"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .help("srfax filename, with or without the |details id"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .requires("to")
+                        .value_parser(parse_date)
+                        .help("first day received, YYYY-MM-DD"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .requires("from")
+                        .value_parser(parse_date)
+                        .help("last day received, YYYY-MM-DD"),
+                )
+                .group(
+                    ArgGroup::new("target")
+                        .args(["file", "from"])
+                        .required(true),
+                ),
+        );
 
+    let app = control::add_to_clap(app);
+    let app = quarantine::add_to_clap(app);
+    let app = history::add_to_clap(app);
+    let app = reconcile::add_to_clap(app);
+    let app = retention::add_to_clap(app);
+    let app = verify::add_to_clap(app);
+    let app = encryption::add_to_clap(app);
+    let app = ocr::add_to_clap(app);
     winservice::add_to_clap(app)
 }
 fn handle_matches(matches: ArgMatches) -> Result<()> {
@@ -30,12 +94,34 @@ fn handle_matches(matches: ArgMatches) -> Result<()> {
         std::process::exit(crate::EXIT_CODE_0);
     }
 
-    let did_match: bool = if let Some(_matches) = matches.subcommand_matches("run") {
+    let did_match: bool = if let Some(matches) = matches.subcommand_matches("run") {
+        if matches.get_flag("once") {
+            let accounts: Vec<String> = matches
+                .get_many::<String>("account")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+
+            let code = crate::run_once(&accounts)?;
+            std::process::exit(code);
+        }
         crate::run_program()?;
 
+        true
+    } else if let Some(matches) = matches.subcommand_matches("redownload") {
+        redownload(matches)?;
+
         true
     } else {
-        winservice::check_clap(&matches)?
+        control::check_clap(&matches)?
+            || quarantine::check_clap(&matches)?
+            || history::check_clap(&matches)?
+            || reconcile::check_clap(&matches)?
+            || retention::check_clap(&matches)?
+            || verify::check_clap(&matches)?
+            || encryption::check_clap(&matches)?
+            || ocr::check_clap(&matches)?
+            || winservice::check_clap(&matches)?
     };
 
     if did_match {
@@ -45,3 +131,37 @@ fn handle_matches(matches: ArgMatches) -> Result<()> {
 
     Ok(())
 }
+
+pub fn parse_date(s: &str) -> std::result::Result<NaiveDate, chrono::ParseError> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+}
+
+fn redownload(matches: &ArgMatches) -> Result<()> {
+    crate::setup_logging()?;
+
+    let account: &String = unwrap!(matches.get_one("account"));
+    let target = if let Some(file) = matches.get_one::<String>("file") {
+        RedownloadTarget::File(file.clone())
+    } else {
+        RedownloadTarget::Range(
+            *unwrap!(matches.get_one::<NaiveDate>("from")),
+            *unwrap!(matches.get_one::<NaiveDate>("to")),
+        )
+    };
+
+    let mut failed = 0;
+    for (item, result) in srfax_service::redownload(account, &target)? {
+        match result {
+            Ok(location) => println!("{}: {}", item.FileName, location),
+            Err(e) => {
+                failed += 1;
+                println!("{}: failed, {}", item.FileName, e);
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} faxes failed to download", failed);
+    }
+
+    Ok(())
+}
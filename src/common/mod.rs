@@ -1,5 +1,8 @@
 pub mod winservice;
 
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 pub fn set_cwd_to_exe() -> std::io::Result<()> {
     let mut path = std::env::current_exe()?;
     path.pop();
@@ -8,3 +11,25 @@ pub fn set_cwd_to_exe() -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// a new directory in the system temp directory only we can read, for
+/// handing faxes to other programs
+pub fn private_temp_dir(prefix: &str) -> std::io::Result<PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "{}-{}-{}",
+        prefix,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+
+    Ok(dir)
+}
@@ -1,4 +1,12 @@
-use crate::{response, EXIT_CODE_0};
+use crate::{
+    archive::ArchiveFormat,
+    barcode::BarcodeRoute,
+    metadata::MetadataFormat,
+    response,
+    storage::{DestinationConfig, StorageConfig},
+    EXIT_CODE_0,
+};
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -25,8 +33,37 @@ pub fn read() -> Result<Config> {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub tick_rate: u64,
+    #[serde(default = "default_health_interval")]
+    pub health_interval: u64,
     pub log: LogConfig,
     pub email: EmailConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default = "default_ledger")]
+    pub ledger: String,
+    #[serde(default = "default_quarantine_after")]
+    pub quarantine_after: u32,
+    /// the tesseract program used for `ocr`
+    #[serde(default = "default_tesseract")]
+    pub tesseract: String,
+    /// the zbarimg program used to read barcodes
+    #[serde(default = "default_zbarimg")]
+    pub zbarimg: String,
+}
+fn default_health_interval() -> u64 {
+    300
+}
+fn default_ledger() -> String {
+    "ledger.sqlite3".to_string()
+}
+fn default_quarantine_after() -> u32 {
+    5
+}
+fn default_tesseract() -> String {
+    "tesseract".to_string()
+}
+fn default_zbarimg() -> String {
+    "zbarimg".to_string()
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LogConfig {
@@ -44,13 +81,141 @@ pub struct EmailConfig {
     pub port: u16,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub socket: String,
+}
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            enabled: cfg!(unix),
+            socket: "srfax-service.sock".to_string(),
+        }
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Srfax {
     pub name: String,
     pub access_id: String,
     pub access_pwd: String,
     pub file_dir: String,
     pub download_fmt: response::DownloadFormat,
+    /// download every fax in each of these formats, just `download_fmt` if
+    /// empty
+    #[serde(default)]
+    pub download_formats: Vec<response::DownloadFormat>,
+    /// also store every fax in these formats, converted from the first
+    /// downloaded one instead of fetched from srfax
+    #[serde(default)]
+    pub convert_to: Vec<response::DownloadFormat>,
     pub delete_after: bool,
+    /// where faxes are written, `file_dir` if not set
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    /// mirror faxes to several places instead of `storage`
+    #[serde(default)]
+    pub destinations: Vec<DestinationConfig>,
+    #[serde(default)]
+    pub delete_delay_days: u32,
+    #[serde(default)]
+    pub remote_retention_days: Option<u32>,
+    /// move faxes received this many days ago into monthly archives
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+    #[serde(default)]
+    pub archive_format: ArchiveFormat,
+    /// where archives go, relative to each destination
+    #[serde(default = "default_archive_dir")]
+    pub archive_dir: String,
+    /// delete faxes and archives received this many days ago
+    #[serde(default)]
+    pub local_retention_days: Option<u32>,
+    /// notify when a destination has less than this many megabytes free
+    #[serde(default)]
+    pub free_space_warn_mb: Option<u64>,
+    /// stop downloading and deleting from srfax while a required destination
+    /// has less than this many megabytes free
+    #[serde(default)]
+    pub free_space_min_mb: Option<u64>,
+    #[serde(default)]
+    pub filename_template: Option<String>,
+    /// e.g. `{file_dir}/{yyyy}/{mm}/{dd}`
+    #[serde(default)]
+    pub dir_template: Option<String>,
+    /// caller id to name, for the `{phonebook}` placeholder
+    #[serde(default)]
+    pub phonebook: HashMap<String, String>,
+    /// write a metadata file next to every fax
+    #[serde(default)]
+    pub sidecar: bool,
+    /// also write a metadata file per fax into this directory
+    #[serde(default)]
+    pub manifest_dir: Option<String>,
+    #[serde(default)]
+    pub metadata_format: MetadataFormat,
+    /// octal mode for faxes and metadata files, e.g. `"0640"`. unix only
+    #[serde(default)]
+    pub file_mode: Option<String>,
+    /// octal mode for directories created for faxes, e.g. `"0750"`
+    #[serde(default)]
+    pub dir_mode: Option<String>,
+    /// group name or gid given to faxes and their directories
+    #[serde(default)]
+    pub group: Option<String>,
+    /// run every downloaded fax through tesseract for a searchable pdf and a
+    /// text file next to it
+    #[serde(default)]
+    pub ocr: bool,
+    /// tesseract languages, e.g. `"eng+fra"`
+    #[serde(default = "default_ocr_language")]
+    pub ocr_language: String,
+    /// read the barcodes on every fax as it is downloaded, for its metadata,
+    /// `{barcode}` and `barcode_routes`
+    #[serde(default)]
+    pub barcodes: bool,
+    /// only scan this many pages for barcodes, all of them if not set
+    #[serde(default)]
+    pub barcode_pages: Option<u32>,
+    /// directories for faxes by their barcode, the first matching one wins
+    #[serde(default)]
+    pub barcode_routes: Vec<BarcodeRoute>,
+    /// age public keys (`age1...`) faxes and metadata files are encrypted
+    /// to before being written
+    #[serde(default)]
+    pub encrypt_to: Vec<String>,
+}
+fn default_archive_dir() -> String {
+    "archive".to_string()
+}
+fn default_ocr_language() -> String {
+    "eng".to_string()
+}
+impl Srfax {
+    /// the formats every fax is stored in, the first one is what the ledger
+    /// records
+    pub fn formats(&self) -> Vec<response::DownloadFormat> {
+        let mut formats = self.fetched_formats();
+        for format in &self.convert_to {
+            if !formats.contains(format) {
+                formats.push(format.clone());
+            }
+        }
+        formats
+    }
+
+    /// the formats every fax is downloaded in from srfax
+    pub fn fetched_formats(&self) -> Vec<response::DownloadFormat> {
+        let mut formats: Vec<response::DownloadFormat> = vec![];
+        for format in &self.download_formats {
+            if !formats.contains(format) {
+                formats.push(format.clone());
+            }
+        }
+        if formats.is_empty() {
+            formats.push(self.download_fmt.clone());
+        }
+        formats
+    }
 }
 
 lazy_static! {
@@ -74,7 +239,8 @@ pub fn check_config_exists() -> Result<()> {
 
 pub fn write_default_config(path: &Path) -> Result<()> {
     let config = Config {
-        tick_rate: 5, // in seconds
+        tick_rate: 5,         // in seconds
+        health_interval: 300, // in seconds
         log: LogConfig {
             level: "info".to_string(),
             dir: None,
@@ -88,6 +254,11 @@ pub fn write_default_config(path: &Path) -> Result<()> {
             domain: String::new(),
             port: 25,
         },
+        control: ControlConfig::default(),
+        ledger: default_ledger(),
+        quarantine_after: default_quarantine_after(),
+        tesseract: default_tesseract(),
+        zbarimg: default_zbarimg(),
     };
     let config_content = serde_json::to_string_pretty(&config)?;
 
@@ -116,7 +287,34 @@ pub fn write_default_srfaxes(path: &Path) -> Result<()> {
         access_pwd: String::new(),
         file_dir: "srfax1".to_string(),
         download_fmt: response::DownloadFormat::PDF,
+        download_formats: vec![],
+        convert_to: vec![],
         delete_after: false,
+        storage: None,
+        destinations: vec![],
+        delete_delay_days: 0,
+        remote_retention_days: None,
+        archive_after_days: None,
+        archive_format: ArchiveFormat::Zip,
+        archive_dir: default_archive_dir(),
+        local_retention_days: None,
+        free_space_warn_mb: None,
+        free_space_min_mb: None,
+        filename_template: None,
+        dir_template: None,
+        phonebook: HashMap::new(),
+        sidecar: false,
+        manifest_dir: None,
+        metadata_format: MetadataFormat::Json,
+        file_mode: None,
+        dir_mode: None,
+        group: None,
+        ocr: false,
+        ocr_language: default_ocr_language(),
+        barcodes: false,
+        barcode_pages: None,
+        barcode_routes: vec![],
+        encrypt_to: vec![],
     }];
 
     let content = serde_json::to_string_pretty(&srfaxes)?;
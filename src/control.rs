@@ -0,0 +1,309 @@
+use crate::state::AccountState;
+use clap::{Arg, ArgMatches, Command};
+use std::sync::mpsc::Sender;
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("Control(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Control(Json({0:?}))")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Control(Config({0:?}))")]
+    Config(#[from] crate::config::ConfigError),
+
+    #[error("Control({0})")]
+    Refused(String),
+}
+type Result<T> = std::result::Result<T, ControlError>;
+
+/// one line of json sent to the control socket
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Request {
+    Poll { account: Option<String> },
+    Pause { account: String },
+    Resume { account: String },
+    Status,
+}
+
+/// one line of json sent back for every request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    pub accounts: Vec<(String, AccountState)>,
+}
+#[cfg(unix)]
+impl Response {
+    fn ok(message: &str) -> Response {
+        Response {
+            ok: true,
+            message: message.to_string(),
+            accounts: vec![],
+        }
+    }
+    fn err(message: &str) -> Response {
+        Response {
+            ok: false,
+            message: message.to_string(),
+            accounts: vec![],
+        }
+    }
+}
+
+/// a poll request for the service loop, `None` polls every account
+pub type PollTrigger = Option<String>;
+
+#[cfg(unix)]
+fn handle_request(req: Request, trigger: &Sender<PollTrigger>) -> Result<Response> {
+    use crate::{config, state};
+
+    let srfaxes = config::get_srfaxes()?;
+    let known = |name: &str| srfaxes.iter().any(|srfax| srfax.name == name);
+
+    let resp = match req {
+        Request::Poll { account } => {
+            if let Some(ref name) = account {
+                if !known(name) {
+                    return Ok(Response::err(&format!("no srfax named {:?}", name)));
+                }
+                if state::get(name).paused {
+                    return Ok(Response::err(&format!("{} is paused", name)));
+                }
+            }
+            if trigger.send(account).is_err() {
+                return Ok(Response::err("service is not polling"));
+            }
+
+            Response::ok("poll triggered")
+        }
+        Request::Pause { account } | Request::Resume { account } if !known(&account) => {
+            Response::err(&format!("no srfax named {:?}", account))
+        }
+        Request::Pause { account } => {
+            state::set_paused(&account, true);
+            Response::ok(&format!("{} paused", account))
+        }
+        Request::Resume { account } => {
+            state::set_paused(&account, false);
+            Response::ok(&format!("{} resumed", account))
+        }
+        Request::Status => Response {
+            ok: true,
+            message: String::new(),
+            accounts: srfaxes
+                .iter()
+                .map(|srfax| (srfax.name.clone(), state::get(&srfax.name)))
+                .collect(),
+        },
+    };
+
+    Ok(resp)
+}
+
+/// binds the socket in a directory only we can enter and moves it into place
+/// once it is 0600, so no one can connect while it has the umask's mode
+#[cfg(unix)]
+fn bind_private(path: &str) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::path::Path;
+
+    let path = Path::new(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir =
+        path.parent()
+            .unwrap_or(Path::new(""))
+            .join(format!(".{}.{}", name, std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let private = dir.join(&*name);
+    let bound = std::os::unix::net::UnixListener::bind(&private).and_then(|listener| {
+        std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&private, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&private);
+    let _ = std::fs::remove_dir(&dir);
+
+    Ok(bound?)
+}
+
+#[cfg(unix)]
+pub fn start_server(trigger: Sender<PollTrigger>) -> Result<()> {
+    use crate::config::CONFIG;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    if !CONFIG.control.enabled {
+        debug!("control socket not enabled");
+        return Ok(());
+    }
+
+    let path = &CONFIG.control.socket;
+    // a socket left behind by a previous run would make bind fail
+    if std::path::Path::new(path).exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(ControlError::Refused(format!(
+                "another instance is listening on {}",
+                path
+            )));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = bind_private(path)?;
+    info!("control socket listening on {}", path);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("error accepting control connection! {:?}", e);
+                    continue;
+                }
+            };
+            let trigger = trigger.clone();
+
+            std::thread::spawn(move || {
+                let mut writer = match stream.try_clone() {
+                    Ok(writer) => writer,
+                    Err(e) => {
+                        warn!("error handling control connection! {:?}", e);
+                        return;
+                    }
+                };
+
+                for line in BufReader::new(stream).lines() {
+                    let resp = match line
+                        .map_err(ControlError::from)
+                        .and_then(|line| Ok(serde_json::from_str::<Request>(&line)?))
+                    {
+                        Ok(req) => {
+                            info!("control request: {:?}", req);
+                            handle_request(req, &trigger)
+                                .unwrap_or_else(|e| Response::err(&e.to_string()))
+                        }
+                        Err(e) => Response::err(&e.to_string()),
+                    };
+
+                    let sent = serde_json::to_string(&resp)
+                        .map_err(ControlError::from)
+                        .and_then(|json| Ok(writeln!(writer, "{}", json)?));
+                    if let Err(e) = sent {
+                        warn!("error answering control request! {:?}", e);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn start_server(_trigger: Sender<PollTrigger>) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn send(req: &Request) -> Result<Response> {
+    use crate::config::CONFIG;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(&CONFIG.control.socket)?;
+    writeln!(stream, "{}", serde_json::to_string(req)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line)?)
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("poll")
+            .about("ask the running service to poll now")
+            .arg(Arg::new("account").help("only poll this account")),
+    )
+    .subcommand(
+        Command::new("pause")
+            .about("stop polling an account until resumed")
+            .arg(Arg::new("account").required(true)),
+    )
+    .subcommand(
+        Command::new("resume")
+            .about("resume polling a paused account")
+            .arg(Arg::new("account").required(true)),
+    )
+    .subcommand(Command::new("status").about("show the state of every account"))
+}
+
+#[cfg(unix)]
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let account = |m: &ArgMatches| m.get_one::<String>("account").cloned();
+
+    let req = if let Some(m) = matches.subcommand_matches("poll") {
+        Request::Poll {
+            account: account(m),
+        }
+    } else if let Some(m) = matches.subcommand_matches("pause") {
+        Request::Pause {
+            account: unwrap!(account(m)),
+        }
+    } else if let Some(m) = matches.subcommand_matches("resume") {
+        Request::Resume {
+            account: unwrap!(account(m)),
+        }
+    } else if matches.subcommand_matches("status").is_some() {
+        Request::Status
+    } else {
+        return Ok(false);
+    };
+
+    let resp = send(&req)?;
+    if !resp.ok {
+        return Err(ControlError::Refused(resp.message));
+    }
+    if !resp.message.is_empty() {
+        println!("{}", resp.message);
+    }
+    for (name, account) in &resp.accounts {
+        println!(
+            "{}: health={:?} paused={} polling={} last_poll={} last_result={}",
+            name,
+            account.health,
+            account.paused,
+            account.polling,
+            account
+                .last_poll
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "never".to_string()),
+            account.last_result.as_deref().unwrap_or("-"),
+        );
+        for (destination, level) in &account.space {
+            if *level != crate::disk_space::SpaceLevel::Ok {
+                println!("  {}: disk space {:?}", destination, level);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    for name in ["poll", "pause", "resume", "status"] {
+        if matches.subcommand_matches(name).is_some() {
+            return Err(ControlError::Refused(
+                "the control socket is only supported on unix".to_string(),
+            ));
+        }
+    }
+
+    Ok(false)
+}
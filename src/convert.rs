@@ -0,0 +1,904 @@
+use crate::response::DownloadFormat;
+use fax::{decoder, encoder::Encoder, Color, VecWriter};
+use lopdf::{dictionary, Document, Object, Stream};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("Convert(Pdf({0:?}))")]
+    Pdf(#[from] lopdf::Error),
+
+    #[error("Convert(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Convert(invalid tiff: {0})")]
+    InvalidTiff(String),
+
+    #[error("Convert(unsupported: {0})")]
+    Unsupported(String),
+}
+type Result<T> = std::result::Result<T, ConvertError>;
+
+/// horizontal resolution of every fax
+const FAX_X_DPI: f32 = 204.0;
+/// vertical resolution of standard faxes
+const STANDARD_Y_DPI: f32 = 98.0;
+/// vertical resolution of fine faxes
+const FINE_Y_DPI: f32 = 196.0;
+
+/// converts a downloaded fax between pdf and tiff, keeping the size of every
+/// page. only black and white scans can be converted, which is all srfax
+/// sends
+pub fn convert(data: &[u8], from: &DownloadFormat, to: &DownloadFormat) -> Result<Vec<u8>> {
+    match (from, to) {
+        (DownloadFormat::TIF, DownloadFormat::PDF) => pdf_from_pages(&tiff_pages(data)?),
+        (DownloadFormat::PDF, DownloadFormat::TIF) => tiff_from_pages(&pdf_pages(data)?),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+/// a tiff of the fax, or its first `max_pages`, with square pixels for ocr
+/// and barcode scanners, which assume them. standard resolution pages get
+/// every row twice
+pub fn square_tiff(
+    data: &[u8],
+    from: &DownloadFormat,
+    max_pages: Option<usize>,
+) -> Result<Vec<u8>> {
+    let pages = match from {
+        DownloadFormat::TIF => tiff_pages(data)?,
+        DownloadFormat::PDF => pdf_pages(data)?,
+    };
+    let pages = pages
+        .into_iter()
+        .take(max_pages.unwrap_or(usize::MAX))
+        .map(Page::squared)
+        .collect::<Result<Vec<Page>>>()?;
+    tiff_from_pages(&pages)
+}
+
+/// one black and white page
+struct Page {
+    width: u32,
+    height: u32,
+    x_dpi: f32,
+    y_dpi: f32,
+    /// one bit per pixel, 1 is black, every row padded to a whole byte
+    bits: Vec<u8>,
+}
+
+impl Page {
+    fn new(width: u32, height: u32, x_dpi: f32, y_dpi: f32) -> Result<Page> {
+        // the fax codecs count pixels in u16
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(ConvertError::Unsupported(format!(
+                "{}x{} pixel page",
+                width, height
+            )));
+        }
+
+        Ok(Page {
+            width,
+            height,
+            x_dpi,
+            y_dpi,
+            bits: vec![0; (width as usize).div_ceil(8) * height as usize],
+        })
+    }
+
+    fn stride(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// copies a row of packed pixels, `black` is the bit value of black
+    fn set_row_bits(&mut self, y: u32, row: &[u8], black: bool) {
+        let stride = self.stride();
+        let start = y as usize * stride;
+        for (i, byte) in row.iter().take(stride).enumerate() {
+            self.bits[start + i] = if black { *byte } else { !*byte };
+        }
+        // padding bits stay white
+        let extra = stride * 8 - self.width as usize;
+        if extra > 0 {
+            self.bits[start + stride - 1] &= 0xff << extra;
+        }
+    }
+
+    /// sets a row from a fax decoder's color changes
+    fn set_row_transitions(&mut self, y: u32, transitions: &[u16], invert: bool) {
+        let start = y as usize * self.stride();
+        for (x, color) in decoder::pels(transitions, self.width as u16).enumerate() {
+            if (color == Color::Black) != invert {
+                self.bits[start + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    fn row(&self, y: u32) -> impl Iterator<Item = Color> + '_ {
+        let start = y as usize * self.stride();
+        (0..self.width as usize).map(move |x| {
+            if self.bits[start + x / 8] & (0x80 >> (x % 8)) != 0 {
+                Color::Black
+            } else {
+                Color::White
+            }
+        })
+    }
+
+    fn encode_g4(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(VecWriter::new());
+        for y in 0..self.height {
+            encoder
+                .encode_line(self.row(y), self.width as u16)
+                .unwrap_or_else(|e| match e {});
+        }
+        encoder.finish().unwrap_or_else(|e| match e {}).finish()
+    }
+
+    /// the page stretched vertically to the horizontal resolution
+    fn squared(self) -> Result<Page> {
+        if (self.x_dpi - self.y_dpi).abs() < 1.0 {
+            return Ok(self);
+        }
+
+        let height = (self.height as f32 * self.x_dpi / self.y_dpi).round() as u32;
+        let mut page = Page::new(self.width, height, self.x_dpi, self.x_dpi)?;
+        let stride = self.stride();
+        for y in 0..height as usize {
+            let from =
+                ((y as f32 * self.y_dpi / self.x_dpi) as usize).min(self.height as usize - 1);
+            page.bits[y * stride..(y + 1) * stride]
+                .copy_from_slice(&self.bits[from * stride..(from + 1) * stride]);
+        }
+        Ok(page)
+    }
+
+    /// width and height in points
+    fn size(&self) -> (f32, f32) {
+        (
+            self.width as f32 * 72.0 / self.x_dpi,
+            self.height as f32 * 72.0 / self.y_dpi,
+        )
+    }
+}
+
+/// a fax whose file doesn't say its resolution is standard unless it would
+/// be far longer than any paper
+fn guess_y_dpi(height: u32) -> f32 {
+    if height as f32 / STANDARD_Y_DPI > 14.0 {
+        FINE_Y_DPI
+    } else {
+        STANDARD_Y_DPI
+    }
+}
+
+fn tiff_pages(data: &[u8]) -> Result<Vec<Page>> {
+    let le = match data.get(0..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return Err(ConvertError::InvalidTiff("not a tiff file".to_string())),
+    };
+    let truncated = || ConvertError::InvalidTiff("truncated".to_string());
+    let u16_at = |at: usize| -> Result<u16> {
+        let b = data.get(at..at + 2).ok_or_else(truncated)?;
+        Ok(if le {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    };
+    let u32_at = |at: usize| -> Result<u32> {
+        let b = data.get(at..at + 4).ok_or_else(truncated)?;
+        Ok(if le {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    };
+
+    let mut pages = vec![];
+    let mut offset = u32_at(4)? as usize;
+    while offset != 0 {
+        let count = u16_at(offset)? as usize;
+        let mut tags: HashMap<u16, Vec<f64>> = HashMap::new();
+
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let (tag, kind, n) = (
+                u16_at(entry)?,
+                u16_at(entry + 2)?,
+                u32_at(entry + 4)? as usize,
+            );
+            let size = match kind {
+                3 => 2,
+                4 => 4,
+                5 => 8,
+                _ => continue,
+            };
+            if n > data.len() / size {
+                return Err(truncated());
+            }
+            let at = if n * size <= 4 {
+                entry + 8
+            } else {
+                u32_at(entry + 8)? as usize
+            };
+
+            let mut values = Vec::with_capacity(n);
+            for j in 0..n {
+                values.push(match kind {
+                    3 => u16_at(at + j * 2)? as f64,
+                    4 => u32_at(at + j * 4)? as f64,
+                    _ => {
+                        let (num, den) = (u32_at(at + j * 8)?, u32_at(at + j * 8 + 4)?);
+                        if den == 0 {
+                            0.0
+                        } else {
+                            num as f64 / den as f64
+                        }
+                    }
+                });
+            }
+            tags.insert(tag, values);
+        }
+        pages.push(tiff_page(data, &tags)?);
+
+        let next = u32_at(offset + 2 + count * 12)? as usize;
+        // a corrupt file can point back at an earlier directory
+        if next != 0 && next <= offset {
+            return Err(ConvertError::InvalidTiff("directories loop".to_string()));
+        }
+        offset = next;
+    }
+
+    if pages.is_empty() {
+        return Err(ConvertError::InvalidTiff("no pages".to_string()));
+    }
+    Ok(pages)
+}
+
+fn tiff_page(data: &[u8], tags: &HashMap<u16, Vec<f64>>) -> Result<Page> {
+    let get = |tag: u16| tags.get(&tag).and_then(|v| v.first()).copied();
+    let missing = |name: &str| ConvertError::InvalidTiff(format!("no {}", name));
+
+    let width = get(256).ok_or_else(|| missing("width"))? as u32;
+    let height = get(257).ok_or_else(|| missing("height"))? as u32;
+    if get(258).unwrap_or(1.0) != 1.0 || get(277).unwrap_or(1.0) != 1.0 {
+        return Err(ConvertError::Unsupported(
+            "tiff pages that aren't black and white".to_string(),
+        ));
+    }
+    let compression = get(259).unwrap_or(1.0) as u32;
+    // photometric 1 has black as 0
+    let invert = get(262).unwrap_or(0.0) == 1.0;
+    let reversed = get(266).unwrap_or(1.0) == 2.0;
+    let t4_options = get(292).unwrap_or(0.0) as u32;
+    let rows_per_strip = (get(278).unwrap_or(height as f64) as u32).clamp(1, height.max(1));
+
+    // resolution unit 1 only gives the aspect ratio, 3 is centimeters
+    let (x_dpi, y_dpi) = match (get(282), get(283), get(296).unwrap_or(2.0)) {
+        (Some(x), Some(y), unit) if x > 0.0 && y > 0.0 && unit == 1.0 => {
+            (FAX_X_DPI, FAX_X_DPI * (y / x) as f32)
+        }
+        (Some(x), Some(y), unit) if x > 0.0 && y > 0.0 && unit == 3.0 => {
+            ((x * 2.54) as f32, (y * 2.54) as f32)
+        }
+        (Some(x), Some(y), _) if x > 0.0 && y > 0.0 => (x as f32, y as f32),
+        _ => (FAX_X_DPI, guess_y_dpi(height)),
+    };
+    let mut page = Page::new(width, height, x_dpi, y_dpi)?;
+
+    let offsets = tags.get(&273).ok_or_else(|| missing("strips"))?;
+    let counts = tags.get(&279).ok_or_else(|| missing("strip sizes"))?;
+    for (i, (&offset, &count)) in offsets.iter().zip(counts).enumerate() {
+        let first_row = i as u32 * rows_per_strip;
+        if first_row >= height {
+            break;
+        }
+        let rows = rows_per_strip.min(height - first_row);
+
+        let strip = data
+            .get(offset as usize..(offset + count) as usize)
+            .ok_or_else(|| ConvertError::InvalidTiff(format!("strip {} is truncated", i)))?;
+        let strip = match reversed {
+            true => Cow::Owned(strip.iter().map(|b| b.reverse_bits()).collect()),
+            false => Cow::Borrowed(strip),
+        };
+
+        match compression {
+            1 => copy_rows(&mut page, first_row, rows, &strip, !invert)?,
+            32773 => copy_rows(&mut page, first_row, rows, &unpack_bits(&strip), !invert)?,
+            3 if t4_options & 1 == 0 => {
+                let mut y = first_row;
+                decoder::decode_g3(strip.iter().copied(), |transitions| {
+                    if y < first_row + rows {
+                        page.set_row_transitions(y, transitions, invert);
+                        y += 1;
+                    }
+                });
+                check_rows(i, y - first_row, rows)?;
+            }
+            4 => {
+                let mut y = first_row;
+                decoder::decode_g4(
+                    strip.iter().copied(),
+                    width as u16,
+                    Some(rows as u16),
+                    |transitions| {
+                        if y < first_row + rows {
+                            page.set_row_transitions(y, transitions, invert);
+                            y += 1;
+                        }
+                    },
+                );
+                check_rows(i, y - first_row, rows)?;
+            }
+            3 => {
+                return Err(ConvertError::Unsupported(
+                    "2d group 3 compressed tiff".to_string(),
+                ))
+            }
+            c => return Err(ConvertError::Unsupported(format!("tiff compression {}", c))),
+        }
+    }
+
+    Ok(page)
+}
+
+fn check_rows(strip: usize, decoded: u32, rows: u32) -> Result<()> {
+    if decoded < rows {
+        return Err(ConvertError::InvalidTiff(format!(
+            "strip {} ended after {} of {} rows",
+            strip, decoded, rows
+        )));
+    }
+    Ok(())
+}
+
+/// copies uncompressed rows into the page, `black` is the bit value of black
+fn copy_rows(page: &mut Page, first_row: u32, rows: u32, data: &[u8], black: bool) -> Result<()> {
+    let stride = page.stride();
+    if data.len() < stride * rows as usize {
+        return Err(ConvertError::InvalidTiff("strip is too short".to_string()));
+    }
+    for (i, row) in data.chunks(stride).take(rows as usize).enumerate() {
+        page.set_row_bits(first_row + i as u32, row, black);
+    }
+    Ok(())
+}
+
+fn unpack_bits(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let n = data[i] as i8;
+        i += 1;
+        match n {
+            0..=127 => {
+                let end = (i + n as usize + 1).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+            -127..=-1 => {
+                if let Some(&b) = data.get(i) {
+                    out.extend(std::iter::repeat_n(b, (1 - n as isize) as usize));
+                }
+                i += 1;
+            }
+            // -128 is a no-op
+            _ => (),
+        }
+    }
+    out
+}
+
+/// a multi-page group 4 tiff, like srfax's own
+fn tiff_from_pages(pages: &[Page]) -> Result<Vec<u8>> {
+    let mut out = b"II*\0\0\0\0\0".to_vec();
+    let mut link = 4;
+
+    for (i, page) in pages.iter().enumerate() {
+        let strip = page.encode_g4();
+        let strip_offset = out.len();
+        out.extend_from_slice(&strip);
+        if out.len() % 2 == 1 {
+            out.push(0);
+        }
+
+        // the two resolutions follow the directory
+        const ENTRIES: usize = 15;
+        let ifd = out.len();
+        let rationals = ifd + 2 + ENTRIES * 12 + 4;
+        let entries: [(u16, u16, u32); ENTRIES] = [
+            (254, 4, 2), // NewSubfileType, a page of a multi-page file
+            (256, 4, page.width),
+            (257, 4, page.height),
+            (258, 3, 1), // BitsPerSample
+            (259, 3, 4), // Compression, group 4
+            (262, 3, 0), // PhotometricInterpretation, white is zero
+            (266, 3, 1), // FillOrder
+            (273, 4, strip_offset as u32),
+            (277, 3, 1), // SamplesPerPixel
+            (278, 4, page.height),
+            (279, 4, strip.len() as u32),
+            (282, 5, rationals as u32),
+            (283, 5, rationals as u32 + 8),
+            (293, 4, 0), // T6Options
+            (296, 3, 2), // ResolutionUnit, inches
+        ];
+
+        out.extend_from_slice(&(ENTRIES as u16).to_le_bytes());
+        for (tag, kind, value) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            match kind {
+                3 => {
+                    out.extend_from_slice(&(value as u16).to_le_bytes());
+                    out.extend_from_slice(&[0, 0]);
+                }
+                _ => out.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+        out.extend_from_slice(&[0; 4]);
+        for dpi in [page.x_dpi, page.y_dpi] {
+            out.extend_from_slice(&((dpi * 100.0).round() as u32).to_le_bytes());
+            out.extend_from_slice(&100u32.to_le_bytes());
+        }
+
+        out[link..link + 4].copy_from_slice(&(ifd as u32).to_le_bytes());
+        link = rationals - 4;
+        debug!("wrote tiff page {} at {}", i + 1, ifd);
+    }
+
+    Ok(out)
+}
+
+/// a pdf with every page as a group 4 image, sized by its resolution
+fn pdf_from_pages(pages: &[Page]) -> Result<Vec<u8>> {
+    let mut doc = Document::with_version("1.4");
+    let pages_id = doc.new_object_id();
+    let mut kids = vec![];
+
+    for page in pages {
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => page.width as i64,
+                "Height" => page.height as i64,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 1,
+                "Filter" => "CCITTFaxDecode",
+                "DecodeParms" => dictionary! {
+                    "K" => -1,
+                    "Columns" => page.width as i64,
+                    "Rows" => page.height as i64,
+                    "BlackIs1" => false,
+                },
+            },
+            page.encode_g4(),
+        );
+        let image_id = doc.add_object(image);
+
+        let (width, height) = page.size();
+        let content = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q", width, height);
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            "Resources" => dictionary! {
+                "XObject" => dictionary! { "Im0" => image_id },
+            },
+            "Contents" => content_id,
+        });
+        kids.push(page_id.into());
+    }
+
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    let mut out = vec![];
+    doc.save_to(&mut out)?;
+    Ok(out)
+}
+
+fn pdf_pages(data: &[u8]) -> Result<Vec<Page>> {
+    let doc = Document::load_mem(data)?;
+
+    let mut pages = vec![];
+    for (number, page_id) in doc.get_pages() {
+        let page = pdf_page(&doc, page_id).map_err(|e| match e {
+            ConvertError::Unsupported(what) => {
+                ConvertError::Unsupported(format!("page {}: {}", number, what))
+            }
+            e => e,
+        })?;
+        pages.push(page);
+    }
+
+    if pages.is_empty() {
+        return Err(ConvertError::Unsupported("pdf without pages".to_string()));
+    }
+    Ok(pages)
+}
+
+/// a page that is a single scanned image, anything else would need a pdf
+/// renderer
+fn pdf_page(doc: &Document, page_id: lopdf::ObjectId) -> Result<Page> {
+    let images = page_images(doc, page_id)?;
+    let image = match images.as_slice() {
+        [image] => *image,
+        _ => {
+            return Err(ConvertError::Unsupported(format!(
+                "{} images instead of one scan",
+                images.len()
+            )))
+        }
+    };
+    let dict = &image.dict;
+    let int = |key: &[u8]| {
+        dict.get_deref(key, doc)
+            .and_then(Object::as_i64)
+            .map_err(ConvertError::from)
+    };
+
+    let (width, height) = (int(b"Width")? as u32, int(b"Height")? as u32);
+    let (page_width, page_height) = media_box(doc, page_id)?;
+    let mut page = Page::new(
+        width,
+        height,
+        width as f32 * 72.0 / page_width,
+        height as f32 * 72.0 / page_height,
+    )?;
+
+    let filters: Vec<Vec<u8>> = match dict.get_deref(b"Filter", doc) {
+        Ok(Object::Name(name)) => vec![name.clone()],
+        Ok(Object::Array(names)) => names
+            .iter()
+            .filter_map(|n| n.as_name().ok().map(|n| n.to_vec()))
+            .collect(),
+        _ => vec![],
+    };
+    let params = match dict.get_deref(b"DecodeParms", doc) {
+        Ok(Object::Dictionary(params)) => Some(params),
+        Ok(Object::Array(params)) => params.last().and_then(|p| p.as_dict().ok()),
+        _ => None,
+    };
+    // a `/Decode [1 0]` swaps black and white
+    let decode_inverted = dict
+        .get_deref(b"Decode", doc)
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|d| d.first())
+        .and_then(|d| d.as_float().ok())
+        .is_some_and(|d| d == 1.0);
+
+    match filters
+        .iter()
+        .map(|f| f.as_slice())
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [b"CCITTFaxDecode"] => {
+            let param = |key: &[u8]| params.and_then(|p| p.get(key).ok());
+            let k = param(b"K").and_then(|k| k.as_i64().ok()).unwrap_or(0);
+            let black_is_1 = param(b"BlackIs1")
+                .and_then(|b| b.as_bool().ok())
+                .unwrap_or(false);
+            let invert = black_is_1 != decode_inverted;
+
+            let mut y = 0;
+            let mut line = |transitions: &[u16]| {
+                if y < height {
+                    page.set_row_transitions(y, transitions, invert);
+                    y += 1;
+                }
+            };
+            match k {
+                k if k < 0 => {
+                    decoder::decode_g4(
+                        image.content.iter().copied(),
+                        width as u16,
+                        Some(height as u16),
+                        &mut line,
+                    );
+                }
+                0 => {
+                    decoder::decode_g3(image.content.iter().copied(), &mut line);
+                }
+                _ => {
+                    return Err(ConvertError::Unsupported(
+                        "2d group 3 compressed image".to_string(),
+                    ))
+                }
+            }
+            if y < height {
+                return Err(ConvertError::Unsupported(format!(
+                    "image ended after {} of {} rows",
+                    y, height
+                )));
+            }
+        }
+        [] | [b"FlateDecode"] => {
+            let bits = int(b"BitsPerComponent").unwrap_or(8);
+            let gray = matches!(dict.get_deref(b"ColorSpace", doc), Ok(Object::Name(n)) if n == b"DeviceGray")
+                || dict
+                    .get(b"ImageMask")
+                    .and_then(Object::as_bool)
+                    .unwrap_or(false);
+            if bits != 1 || !gray {
+                return Err(ConvertError::Unsupported(format!(
+                    "{} bit color image",
+                    bits
+                )));
+            }
+
+            let raw = match filters.is_empty() {
+                true => image.content.clone(),
+                false => {
+                    let mut raw = vec![];
+                    flate2::read::ZlibDecoder::new(image.content.as_slice())
+                        .read_to_end(&mut raw)?;
+                    raw
+                }
+            };
+            // in gray 0 is black
+            copy_rows(&mut page, 0, height, &raw, decode_inverted).map_err(|_| {
+                ConvertError::Unsupported("image data is shorter than the image".to_string())
+            })?;
+        }
+        filters => {
+            return Err(ConvertError::Unsupported(format!(
+                "images compressed with {:?}",
+                filters
+                    .iter()
+                    .map(|f| String::from_utf8_lossy(f))
+                    .collect::<Vec<_>>()
+            )))
+        }
+    }
+
+    Ok(page)
+}
+
+/// the image xobjects of a page, from its own resources or inherited ones
+fn page_images(doc: &Document, page_id: lopdf::ObjectId) -> Result<Vec<&Stream>> {
+    let (own, inherited) = doc.get_page_resources(page_id)?;
+    let mut resources: Vec<&lopdf::Dictionary> = own.into_iter().collect();
+    resources.extend(
+        inherited
+            .into_iter()
+            .filter_map(|id| doc.get_dictionary(id).ok()),
+    );
+
+    let mut images = vec![];
+    for resources in resources {
+        let xobjects = match resources
+            .get_deref(b"XObject", doc)
+            .and_then(Object::as_dict)
+        {
+            Ok(xobjects) => xobjects,
+            Err(_) => continue,
+        };
+        for (_, xobject) in xobjects.iter() {
+            let stream = match doc.dereference(xobject).and_then(|(_, o)| o.as_stream()) {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image") {
+                images.push(stream);
+            }
+        }
+        // the page's own resources override its parents'
+        if !images.is_empty() {
+            break;
+        }
+    }
+
+    Ok(images)
+}
+
+/// width and height of a page in points, which may be set on a parent
+fn media_box(doc: &Document, page_id: lopdf::ObjectId) -> Result<(f32, f32)> {
+    let mut node = doc.get_dictionary(page_id)?;
+    for _ in 0..32 {
+        if let Ok(media_box) = node.get_deref(b"MediaBox", doc).and_then(Object::as_array) {
+            let n: Vec<f32> = media_box
+                .iter()
+                .filter_map(|v| v.as_float().ok().or(v.as_i64().ok().map(|v| v as f32)))
+                .collect();
+            if let [x0, y0, x1, y1] = n.as_slice() {
+                let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+                if width > 0.0 && height > 0.0 {
+                    return Ok((width, height));
+                }
+            }
+        }
+        node = match node.get(b"Parent").and_then(Object::as_reference) {
+            Ok(parent) => doc.get_dictionary(parent)?,
+            Err(_) => break,
+        };
+    }
+
+    // a letter page
+    Ok((612.0, 792.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a page with a diagonal stripe and a box, so rows differ
+    fn page(width: u32, height: u32, y_dpi: f32) -> Page {
+        let mut page = Page::new(width, height, FAX_X_DPI, y_dpi).unwrap();
+        for y in 0..height {
+            let mut row = vec![0; page.stride()];
+            for x in 0..width {
+                let stripe = (x + y) % 97 < 9;
+                let square = (40..120).contains(&x) && (20..60).contains(&y);
+                if stripe || square {
+                    row[x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            page.set_row_bits(y, &row, true);
+        }
+        page
+    }
+
+    /// where the value of a tag in the first directory of a tiff written by
+    /// `tiff_from_pages`, and where the next directory's offset is
+    fn tag_at(tiff: &[u8], tag: u16) -> (usize, usize) {
+        let ifd = u32::from_le_bytes(tiff[4..8].try_into().unwrap()) as usize;
+        let count = u16::from_le_bytes([tiff[ifd], tiff[ifd + 1]]) as usize;
+        let entry = (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| u16::from_le_bytes([tiff[entry], tiff[entry + 1]]) == tag);
+        (entry.unwrap() + 8, ifd + 2 + count * 12)
+    }
+
+    fn invalid_tiff(result: Result<Vec<Page>>) -> String {
+        match result {
+            Err(ConvertError::InvalidTiff(why)) => why,
+            Err(e) => panic!("expected an invalid tiff, got {:?}", e),
+            Ok(_) => panic!("expected an invalid tiff"),
+        }
+    }
+
+    #[test]
+    fn tiff_pdf_tiff_round_trip() {
+        let tiff = tiff_from_pages(&[page(1728, 300, FINE_Y_DPI), page(1728, 150, STANDARD_Y_DPI)])
+            .unwrap();
+
+        let pdf = convert(&tiff, &DownloadFormat::TIF, &DownloadFormat::PDF).unwrap();
+        let back = convert(&pdf, &DownloadFormat::PDF, &DownloadFormat::TIF).unwrap();
+        assert_eq!(tiff, back);
+
+        let pages = pdf_pages(&pdf).unwrap();
+        assert_eq!(pages.len(), 2);
+        for (page, expected) in pages
+            .iter()
+            .zip([page(1728, 300, FINE_Y_DPI), page(1728, 150, STANDARD_Y_DPI)])
+        {
+            assert_eq!((page.width, page.height), (expected.width, expected.height));
+            assert!((page.y_dpi - expected.y_dpi).abs() < 0.5);
+            assert!(page.bits == expected.bits);
+        }
+    }
+
+    #[test]
+    fn squares_standard_pages() {
+        let tiff = tiff_from_pages(&[
+            page(1728, 100, STANDARD_Y_DPI),
+            page(1728, 200, FINE_Y_DPI),
+            page(1728, 100, STANDARD_Y_DPI),
+        ])
+        .unwrap();
+
+        let squared = square_tiff(&tiff, &DownloadFormat::TIF, Some(2)).unwrap();
+        let pages = tiff_pages(&squared).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].height, 208);
+        assert_eq!(pages[1].height, 208);
+        assert!(pages.iter().all(|page| page.y_dpi == FAX_X_DPI));
+    }
+
+    #[test]
+    fn reads_uncompressed_and_packbits_strips() {
+        let expected = page(64, 4, FINE_Y_DPI);
+        let mut tiff = tiff_from_pages(&[page(64, 4, FINE_Y_DPI)]).unwrap();
+        let (compression, _) = tag_at(&tiff, 259);
+        let (offset, _) = tag_at(&tiff, 273);
+        let (count, _) = tag_at(&tiff, 279);
+
+        // each row as a packbits literal run
+        let mut packed = vec![];
+        for row in expected.bits.chunks(expected.stride()) {
+            packed.push(row.len() as u8 - 1);
+            packed.extend_from_slice(row);
+        }
+        let strip = tiff.len() as u32;
+        tiff.extend_from_slice(&packed);
+        tiff[compression..compression + 2].copy_from_slice(&32773u16.to_le_bytes());
+        tiff[offset..offset + 4].copy_from_slice(&strip.to_le_bytes());
+        tiff[count..count + 4].copy_from_slice(&(packed.len() as u32).to_le_bytes());
+        assert!(tiff_pages(&tiff).unwrap()[0].bits == expected.bits);
+
+        let strip = tiff.len() as u32;
+        tiff.extend_from_slice(&expected.bits);
+        tiff[compression..compression + 2].copy_from_slice(&1u16.to_le_bytes());
+        tiff[offset..offset + 4].copy_from_slice(&strip.to_le_bytes());
+        tiff[count..count + 4].copy_from_slice(&(expected.bits.len() as u32).to_le_bytes());
+        assert!(tiff_pages(&tiff).unwrap()[0].bits == expected.bits);
+    }
+
+    #[test]
+    fn unpacks_repeat_runs() {
+        // a literal run of 2, -128 which does nothing, and 0xff three times
+        assert_eq!(
+            unpack_bits(&[1, 0x12, 0x34, 0x80, 0xfe, 0xff]),
+            [0x12, 0x34, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(unpack_bits(&[0x81, 0]), [0; 128]);
+        // a repeat run cut off before its byte
+        assert!(unpack_bits(&[0xfe]).is_empty());
+
+        // a blank row and a row of stripes, as repeat runs
+        let mut expected = Page::new(64, 2, FAX_X_DPI, FINE_Y_DPI).unwrap();
+        expected.set_row_bits(1, &[0xaa; 8], true);
+        let mut tiff =
+            tiff_from_pages(&[Page::new(64, 2, FAX_X_DPI, FINE_Y_DPI).unwrap()]).unwrap();
+        let (compression, _) = tag_at(&tiff, 259);
+        let (offset, _) = tag_at(&tiff, 273);
+        let (count, _) = tag_at(&tiff, 279);
+
+        let packed = [0xf9, 0x00, 0xf9, 0xaa];
+        let strip = tiff.len() as u32;
+        tiff.extend_from_slice(&packed);
+        tiff[compression..compression + 2].copy_from_slice(&32773u16.to_le_bytes());
+        tiff[offset..offset + 4].copy_from_slice(&strip.to_le_bytes());
+        tiff[count..count + 4].copy_from_slice(&(packed.len() as u32).to_le_bytes());
+        assert!(tiff_pages(&tiff).unwrap()[0].bits == expected.bits);
+    }
+
+    #[test]
+    fn truncated_strip() {
+        let mut tiff = tiff_from_pages(&[page(1728, 100, FINE_Y_DPI)]).unwrap();
+        let (count, _) = tag_at(&tiff, 279);
+        let len = tiff.len() as u32;
+        tiff[count..count + 4].copy_from_slice(&len.to_le_bytes());
+
+        assert_eq!(invalid_tiff(tiff_pages(&tiff)), "strip 0 is truncated");
+    }
+
+    #[test]
+    fn looping_directories() {
+        let mut tiff = tiff_from_pages(&[page(1728, 100, FINE_Y_DPI)]).unwrap();
+        let (_, next) = tag_at(&tiff, 256);
+        let ifd: [u8; 4] = tiff[4..8].try_into().unwrap();
+        tiff[next..next + 4].copy_from_slice(&ifd);
+
+        assert_eq!(invalid_tiff(tiff_pages(&tiff)), "directories loop");
+    }
+
+    #[test]
+    fn not_a_tiff() {
+        assert_eq!(invalid_tiff(tiff_pages(b"%PDF-1.4")), "not a tiff file");
+        assert_eq!(invalid_tiff(tiff_pages(b"II*\0\x08\0")), "truncated");
+    }
+}
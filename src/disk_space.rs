@@ -0,0 +1,146 @@
+use crate::{config::Srfax, email, state, storage::Destination};
+
+const MB: u64 = 1024 * 1024;
+
+/// how much space a destination has left compared to its thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SpaceLevel {
+    Ok,
+    /// below `free_space_warn_mb`
+    Low,
+    /// below `free_space_min_mb`
+    Critical,
+}
+
+impl SpaceLevel {
+    /// the level of `free` MB, below a threshold means strictly less
+    fn of(free: u64, warn_mb: Option<u64>, min_mb: Option<u64>) -> SpaceLevel {
+        if min_mb.is_some_and(|min| free < min) {
+            SpaceLevel::Critical
+        } else if warn_mb.is_some_and(|warn| free < warn) {
+            SpaceLevel::Low
+        } else {
+            SpaceLevel::Ok
+        }
+    }
+}
+
+/// checks the free space of every destination with thresholds, notifying
+/// whenever one crosses a threshold. returns whether downloads and deletes
+/// from srfax have to stop, because a required destination is below
+/// `free_space_min_mb`. destinations that can't tell how much space they
+/// have are not checked
+pub fn check(srfax: &Srfax, destinations: &[Destination]) -> bool {
+    let mut suspend = false;
+
+    for destination in destinations {
+        let (warn_mb, min_mb) = (
+            destination.free_space_warn_mb,
+            destination.free_space_min_mb,
+        );
+        if warn_mb.is_none() && min_mb.is_none() {
+            continue;
+        }
+        let free = match destination
+            .storage()
+            .and_then(|storage| storage.free_space())
+        {
+            Ok(Some(free)) => free / MB,
+            Ok(None) => {
+                debug!(
+                    "free space of {} of {} is unknown, not checking it",
+                    destination.name, srfax.name
+                );
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "could not check free space! name={} destination={} error={:?}",
+                    srfax.name, destination.name, e
+                );
+                continue;
+            }
+        };
+
+        let level = SpaceLevel::of(free, warn_mb, min_mb);
+        suspend |= level == SpaceLevel::Critical && destination.required;
+
+        if state::set_space_level(&srfax.name, &destination.name, level) != level {
+            notify(srfax, destination, level, free);
+        }
+    }
+
+    suspend
+}
+
+/// one email per threshold crossed, not one per poll
+fn notify(srfax: &Srfax, destination: &Destination, level: SpaceLevel, free: u64) {
+    let message = match level {
+        SpaceLevel::Ok => {
+            let message = format!(
+                "destination {} of {} has {} MB free again",
+                destination.name, srfax.name, free
+            );
+            info!("{}", message);
+            email::send_email_fork("disk space recovered", &message);
+            return;
+        }
+        SpaceLevel::Low => format!(
+            "destination {} of {} has {} MB free, below free_space_warn_mb {}",
+            destination.name,
+            srfax.name,
+            free,
+            destination.free_space_warn_mb.unwrap_or_default()
+        ),
+        SpaceLevel::Critical if destination.required => format!(
+            "destination {} of {} has {} MB free, below free_space_min_mb {}. downloads and \
+            deletes from srfax are suspended until there is more space",
+            destination.name,
+            srfax.name,
+            free,
+            destination.free_space_min_mb.unwrap_or_default()
+        ),
+        SpaceLevel::Critical => format!(
+            "optional destination {} of {} has {} MB free, below free_space_min_mb {}",
+            destination.name,
+            srfax.name,
+            free,
+            destination.free_space_min_mb.unwrap_or_default()
+        ),
+    };
+
+    warn!("{}", message);
+    email::send_email_fork("low disk space", &message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_at_thresholds() {
+        let level = |free| SpaceLevel::of(free, Some(500), Some(100));
+        assert_eq!(level(501), SpaceLevel::Ok);
+        assert_eq!(level(500), SpaceLevel::Ok);
+        assert_eq!(level(499), SpaceLevel::Low);
+        assert_eq!(level(100), SpaceLevel::Low);
+        // critical is what pauses a required destination
+        assert_eq!(level(99), SpaceLevel::Critical);
+        assert_eq!(level(0), SpaceLevel::Critical);
+    }
+
+    #[test]
+    fn levels_with_one_threshold() {
+        assert_eq!(SpaceLevel::of(99, Some(100), None), SpaceLevel::Low);
+        assert_eq!(SpaceLevel::of(0, Some(100), None), SpaceLevel::Low);
+        assert_eq!(SpaceLevel::of(100, None, Some(100)), SpaceLevel::Ok);
+        assert_eq!(SpaceLevel::of(99, None, Some(100)), SpaceLevel::Critical);
+        assert_eq!(SpaceLevel::of(0, None, None), SpaceLevel::Ok);
+
+        // a minimum above the warning still pauses
+        assert_eq!(
+            SpaceLevel::of(300, Some(100), Some(500)),
+            SpaceLevel::Critical
+        );
+    }
+}
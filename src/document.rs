@@ -0,0 +1,88 @@
+use crate::response::DownloadFormat;
+
+/// counts the pages of a downloaded fax, `None` if the count can't be
+/// worked out without fully parsing the file
+pub fn page_count(data: &[u8], fmt: &DownloadFormat) -> Option<usize> {
+    match fmt {
+        DownloadFormat::PDF => pdf_page_count(data),
+        DownloadFormat::TIF => tiff_page_count(data),
+    }
+}
+
+/// counts `/Type /Page` objects, which misses pages hidden in compressed
+/// object streams, so zero means unknown
+fn pdf_page_count(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(b"%PDF") {
+        return None;
+    }
+
+    let mut count = 0;
+    let mut rest = data;
+    while let Some(i) = find(rest, b"/Type") {
+        rest = &rest[i + 5..];
+        let value = trim_start(rest);
+        if value.starts_with(b"/Page") && !value[5..].starts_with(b"s") {
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        Some(count)
+    } else {
+        None
+    }
+}
+
+/// counts the image file directories, one per page
+fn tiff_page_count(data: &[u8]) -> Option<usize> {
+    let read_u16 = |b: &[u8], le: bool| -> u16 {
+        if le {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        }
+    };
+    let read_u32 = |b: &[u8], le: bool| -> u32 {
+        if le {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        }
+    };
+
+    let le = match data.get(0..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+
+    let mut count = 0;
+    let mut offset = read_u32(data.get(4..8)?, le) as usize;
+    while offset != 0 {
+        let entries = read_u16(data.get(offset..offset + 2)?, le) as usize;
+        let next = offset + 2 + entries * 12;
+        let next_offset = read_u32(data.get(next..next + 4)?, le) as usize;
+
+        count += 1;
+        // a corrupt file can point back at an earlier directory
+        if next_offset != 0 && next_offset <= offset {
+            return None;
+        }
+        offset = next_offset;
+    }
+
+    Some(count)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn trim_start(data: &[u8]) -> &[u8] {
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+
+    &data[start..]
+}
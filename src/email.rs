@@ -3,7 +3,8 @@ use chrono::Local;
 use lettre::{
     message::header::ContentType, transport::smtp::client::Tls, Message, SmtpTransport, Transport,
 };
-use std::thread;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
 
 #[derive(Debug, Error)]
 pub enum EmailError {
@@ -15,10 +16,31 @@ pub enum EmailError {
 }
 type Result<T> = std::result::Result<T, EmailError>;
 
+lazy_static! {
+    static ref PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+}
+
 pub fn send_email_fork(in_subject: &str, in_message: &str) {
     let subject: String = in_subject.to_owned();
     let message: String = in_message.to_owned();
-    thread::spawn(move || send_email(&subject, &message));
+    let handle = thread::spawn(move || {
+        if let Err(e) = send_email(&subject, &message) {
+            warn!("error sending email! {:?}", e);
+        }
+    });
+
+    let mut pending = unwrap!(PENDING.lock());
+    pending.retain(|h| !h.is_finished());
+    pending.push(handle);
+}
+
+/// blocks until every forked email has been sent, so nothing is lost on exit
+pub fn wait_for_pending() {
+    let pending: Vec<JoinHandle<()>> = unwrap!(PENDING.lock()).drain(..).collect();
+
+    for handle in pending {
+        let _ = handle.join();
+    }
 }
 
 pub fn send_email(in_subject: &str, in_message: &str) -> Result<()> {
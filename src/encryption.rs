@@ -0,0 +1,254 @@
+use crate::{
+    config::{self, ConfigError, Srfax},
+    storage::{Permissions, StorageError},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("Encryption(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Encryption(Config({0:?}))")]
+    Config(#[from] ConfigError),
+
+    #[error("Encryption(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Encryption(invalid recipient {0:?}: {1})")]
+    InvalidRecipient(String, String),
+
+    #[error("Encryption(Encrypt({0}))")]
+    Encrypt(#[from] age::EncryptError),
+
+    #[error("Encryption(Decrypt({0}))")]
+    Decrypt(#[from] age::DecryptError),
+
+    #[error("Encryption(no identities in {0})")]
+    NoIdentities(String),
+
+    #[error("Encryption({0} is not encrypted, it doesn't end in {ENCRYPTED_SUFFIX})")]
+    NotEncrypted(String),
+
+    #[error("Encryption({0} already exists)")]
+    Exists(String),
+
+    #[error("Encryption(no srfax named {0:?})")]
+    UnknownAccount(String),
+}
+type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// appended to the name of every file written encrypted
+pub const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// the age recipients of an account, `None` if it doesn't encrypt
+pub fn recipients(srfax: &Srfax) -> Result<Option<Vec<age::x25519::Recipient>>> {
+    if srfax.encrypt_to.is_empty() {
+        return Ok(None);
+    }
+
+    let recipients = srfax
+        .encrypt_to
+        .iter()
+        .map(|r| {
+            r.trim()
+                .parse::<age::x25519::Recipient>()
+                .map_err(|e| EncryptionError::InvalidRecipient(r.clone(), e.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Some(recipients))
+}
+
+/// `.age` if the account encrypts, nothing otherwise
+pub fn suffix(srfax: &Srfax) -> &'static str {
+    if srfax.encrypt_to.is_empty() {
+        ""
+    } else {
+        ENCRYPTED_SUFFIX
+    }
+}
+
+/// whether a stored key or path was written encrypted
+pub fn is_encrypted(key: &str) -> bool {
+    key.ends_with(ENCRYPTED_SUFFIX)
+}
+
+/// encrypts `data` to the account's recipients, or returns it as is if the
+/// account doesn't encrypt
+pub fn seal(srfax: &Srfax, data: &[u8]) -> Result<Vec<u8>> {
+    let recipients = match recipients(srfax)? {
+        Some(recipients) => recipients,
+        None => return Ok(data.to_vec()),
+    };
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+    let mut out = Vec::with_capacity(data.len() + 1024);
+    let mut writer = encryptor.wrap_output(&mut out)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+
+    Ok(out)
+}
+
+/// the identities in an age identity file, like one from `age-keygen`
+pub fn read_identities(path: &str) -> Result<Vec<Box<dyn age::Identity>>> {
+    let identities = age::IdentityFile::from_file(path.to_string())?.into_identities()?;
+    if identities.is_empty() {
+        return Err(EncryptionError::NoIdentities(path.to_string()));
+    }
+    Ok(identities)
+}
+
+pub fn decrypt(identities: &[Box<dyn age::Identity>], data: &[u8]) -> Result<Vec<u8>> {
+    let decryptor = age::Decryptor::new(data)?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref()))?;
+
+    let mut out = vec![];
+    reader.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// decrypts `file` next to itself without the `.age`, never overwriting an
+/// existing file, returns where it was written. like a stored fax it gets
+/// the account's `file_mode`, without one only its owner can read it
+fn decrypt_file(
+    identities: &[Box<dyn age::Identity>],
+    file: &str,
+    permissions: &Permissions,
+) -> Result<String> {
+    let dest = file
+        .strip_suffix(ENCRYPTED_SUFFIX)
+        .ok_or_else(|| EncryptionError::NotEncrypted(file.to_string()))?;
+
+    let data = decrypt(identities, &std::fs::read(file)?)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut out = match options.open(dest) {
+        Ok(out) => out,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(EncryptionError::Exists(dest.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    permissions.apply_file(&out)?;
+    out.write_all(&data)?;
+
+    Ok(dest.to_string())
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("decrypt")
+            .about("decrypt faxes and metadata files written with encrypt_to")
+            .arg(
+                Arg::new("identity")
+                    .long("identity")
+                    .short('i')
+                    .required(true)
+                    .help("age identity file holding the private key"),
+            )
+            .arg(
+                Arg::new("account")
+                    .long("account")
+                    .help("write with the file_mode and group of this account"),
+            )
+            .arg(
+                Arg::new("stdout")
+                    .long("stdout")
+                    .action(ArgAction::SetTrue)
+                    .help("write to stdout instead of next to the file"),
+            )
+            .arg(Arg::new("file").required(true).num_args(1..)),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("decrypt") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+    let identities = read_identities(unwrap!(matches.get_one::<String>("identity")))?;
+    let permissions = match matches.get_one::<String>("account") {
+        Some(name) => {
+            let srfax = config::get_srfaxes()?
+                .into_iter()
+                .find(|srfax| srfax.name == *name)
+                .ok_or_else(|| EncryptionError::UnknownAccount(name.clone()))?;
+            Permissions::new(&srfax)?
+        }
+        None => Permissions::default(),
+    };
+
+    for file in matches.get_many::<String>("file").unwrap_or_default() {
+        if matches.get_flag("stdout") {
+            let data = decrypt(&identities, &std::fs::read(file)?)?;
+            std::io::stdout().write_all(&data)?;
+            continue;
+        }
+
+        match decrypt_file(&identities, file, &permissions) {
+            Ok(dest) => eprintln!("decrypted {} to {}", file, dest),
+            Err(e) => eprintln!("could not decrypt {}: {}", file, e),
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common;
+
+    #[cfg(unix)]
+    #[test]
+    fn decrypts_with_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let identity = age::x25519::Identity::generate();
+        let srfax: Srfax = serde_json::from_value(serde_json::json!({
+            "name": "clinic",
+            "access_id": "1",
+            "access_pwd": "pwd",
+            "file_dir": "/srv/faxes",
+            "download_fmt": "PDF",
+            "delete_after": false,
+            "encrypt_to": [identity.to_public().to_string()],
+        }))
+        .unwrap();
+        let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(identity)];
+        let dir = common::private_temp_dir("srfax-test").unwrap();
+        let sealed = seal(&srfax, b"fax").unwrap();
+        let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let file = dir.join("a.PDF.age").to_string_lossy().to_string();
+        std::fs::write(&file, &sealed).unwrap();
+        let dest = decrypt_file(&identities, &file, &Permissions::default()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"fax");
+        assert_eq!(mode(&dest), 0o600);
+        assert!(matches!(
+            decrypt_file(&identities, &file, &Permissions::default()),
+            Err(EncryptionError::Exists(_))
+        ));
+
+        let file = dir.join("b.PDF.age").to_string_lossy().to_string();
+        std::fs::write(&file, &sealed).unwrap();
+        let permissions = Permissions {
+            file_mode: Some(0o640),
+            ..Permissions::default()
+        };
+        let dest = decrypt_file(&identities, &file, &permissions).unwrap();
+        assert_eq!(mode(&dest), 0o640);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
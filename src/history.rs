@@ -0,0 +1,136 @@
+use crate::{
+    cli::parse_date,
+    ledger::{FaxRecord, Ledger, LedgerError, Search},
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("History(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("History(Json({0:?}))")]
+    Json(#[from] serde_json::Error),
+}
+type Result<T> = std::result::Result<T, HistoryError>;
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("history")
+            .about("search received faxes")
+            .arg(Arg::new("account").long("account"))
+            .arg(
+                Arg::new("from")
+                    .long("from")
+                    .value_parser(parse_date)
+                    .help("first day received, YYYY-MM-DD"),
+            )
+            .arg(
+                Arg::new("to")
+                    .long("to")
+                    .value_parser(parse_date)
+                    .help("last day received, YYYY-MM-DD"),
+            )
+            .arg(
+                Arg::new("caller")
+                    .long("caller")
+                    .help("part of the caller id"),
+            )
+            .arg(
+                Arg::new("remote")
+                    .long("remote")
+                    .help("part of the remote id"),
+            )
+            .arg(
+                Arg::new("pages")
+                    .long("pages")
+                    .value_parser(clap::value_parser!(i64)),
+            )
+            .arg(
+                Arg::new("barcode")
+                    .long("barcode")
+                    .help("part of a barcode on the fax"),
+            )
+            .arg(
+                Arg::new("json")
+                    .long("json")
+                    .action(ArgAction::SetTrue)
+                    .help("print json instead of a table"),
+            ),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("history") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+
+    let search = Search {
+        account: matches.get_one::<String>("account").cloned(),
+        from: matches.get_one::<NaiveDate>("from").copied(),
+        to: matches.get_one::<NaiveDate>("to").copied(),
+        caller_id: matches.get_one::<String>("caller").cloned(),
+        remote_id: matches.get_one::<String>("remote").cloned(),
+        pages: matches.get_one::<i64>("pages").copied(),
+        barcode: matches.get_one::<String>("barcode").cloned(),
+    };
+    let records = Ledger::open()?.search(&search)?;
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    } else {
+        print_table(&records);
+    }
+
+    Ok(true)
+}
+
+fn print_table(records: &[FaxRecord]) {
+    let header = [
+        "RECEIVED",
+        "ACCOUNT",
+        "CALLER ID",
+        "REMOTE ID",
+        "PAGES",
+        "PATH",
+    ];
+    let rows: Vec<[String; 6]> = records
+        .iter()
+        .map(|record| {
+            [
+                record
+                    .received_at
+                    .clone()
+                    .unwrap_or_else(|| record.received.clone()),
+                record.account.clone(),
+                record.caller_id.clone(),
+                record.remote_id.clone(),
+                record.pages.map(|p| p.to_string()).unwrap_or_default(),
+                record.local_path.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.each_ref().map(|s| s.as_str()));
+    }
+}
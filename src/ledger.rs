@@ -0,0 +1,1034 @@
+use crate::{barcode::Barcode, config::CONFIG, response::InboxItem, srfax};
+use chrono::{Local, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::time::Duration;
+
+#[derive(Debug, Error)]
+pub enum LedgerError {
+    #[error("Ledger(Sqlite({0:?}))")]
+    Sqlite(#[from] rusqlite::Error),
+}
+type Result<T> = std::result::Result<T, LedgerError>;
+
+/// each entry upgrades the schema by one version, tracked in `user_version`
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE faxes (
+        account TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        details_id TEXT NOT NULL,
+        caller_id TEXT NOT NULL,
+        remote_id TEXT NOT NULL,
+        pages INTEGER,
+        size INTEGER,
+        received TEXT NOT NULL,
+        sha256 TEXT,
+        local_path TEXT,
+        first_seen TEXT NOT NULL,
+        downloaded_at TEXT,
+        PRIMARY KEY (account, filename)
+    );",
+    "ALTER TABLE faxes ADD COLUMN verified_at TEXT;
+    ALTER TABLE faxes ADD COLUMN deleted_at TEXT;",
+    "CREATE TABLE fax_versions (
+        account TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        local_path TEXT NOT NULL,
+        sha256 TEXT NOT NULL,
+        replaced_at TEXT NOT NULL
+    );",
+    "ALTER TABLE faxes ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE faxes ADD COLUMN last_error TEXT;
+    ALTER TABLE faxes ADD COLUMN quarantined_at TEXT;",
+    "ALTER TABLE faxes ADD COLUMN received_at TEXT;",
+    "CREATE TABLE fax_destinations (
+        account TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        location TEXT,
+        sha256 TEXT,
+        stored_at TEXT,
+        last_error TEXT,
+        PRIMARY KEY (account, filename, destination)
+    );
+    INSERT INTO fax_destinations (account, filename, destination, location, sha256, stored_at)
+        SELECT account, filename, 'default', local_path, sha256, downloaded_at
+        FROM faxes WHERE downloaded_at IS NOT NULL;",
+    // every format of a fax is stored separately, faxes so far only had the
+    // one in their extension
+    "CREATE TABLE fax_copies (
+        account TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        format TEXT NOT NULL,
+        location TEXT,
+        sha256 TEXT,
+        stored_at TEXT,
+        last_error TEXT,
+        PRIMARY KEY (account, filename, destination, format)
+    );
+    INSERT INTO fax_copies
+        SELECT account, filename, destination, upper(substr(location, -3)),
+            location, sha256, stored_at, last_error
+        FROM fax_destinations WHERE location IS NOT NULL;
+    DROP TABLE fax_destinations;
+    ALTER TABLE fax_copies RENAME TO fax_destinations;",
+    "ALTER TABLE fax_destinations ADD COLUMN archive TEXT;
+    ALTER TABLE fax_destinations ADD COLUMN archived_at TEXT;
+    ALTER TABLE fax_destinations ADD COLUMN purged_at TEXT;
+    CREATE TABLE archives (
+        account TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        location TEXT NOT NULL,
+        sha256 TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY (account, destination, location)
+    );",
+    "ALTER TABLE faxes ADD COLUMN ocr_at TEXT;
+    ALTER TABLE faxes ADD COLUMN ocr_failures INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE faxes ADD COLUMN ocr_error TEXT;",
+    "ALTER TABLE faxes ADD COLUMN barcodes TEXT;",
+];
+
+/// everything known about a single fax of an account
+#[derive(Debug, Clone, Serialize)]
+pub struct FaxRecord {
+    pub account: String,
+    pub filename: String,
+    pub details_id: String,
+    pub caller_id: String,
+    pub remote_id: String,
+    pub pages: Option<i64>,
+    pub size: Option<i64>,
+    pub received: String,
+    pub received_at: Option<String>,
+    pub sha256: Option<String>,
+    pub local_path: Option<String>,
+    pub first_seen: String,
+    pub downloaded_at: Option<String>,
+    pub verified_at: Option<String>,
+    pub deleted_at: Option<String>,
+    pub failures: i64,
+    pub last_error: Option<String>,
+    pub quarantined_at: Option<String>,
+    /// read from the fax when it was downloaded, if `barcodes` is set
+    pub barcodes: Vec<Barcode>,
+}
+impl FaxRecord {
+    const COLUMNS: &'static str = "account, filename, details_id, caller_id, remote_id, pages, \
+        size, received, received_at, sha256, local_path, first_seen, downloaded_at, verified_at, deleted_at, \
+        failures, last_error, quarantined_at, barcodes";
+
+    fn from_row(row: &Row) -> rusqlite::Result<FaxRecord> {
+        Ok(FaxRecord {
+            account: row.get(0)?,
+            filename: row.get(1)?,
+            details_id: row.get(2)?,
+            caller_id: row.get(3)?,
+            remote_id: row.get(4)?,
+            pages: row.get(5)?,
+            size: row.get(6)?,
+            received: row.get(7)?,
+            received_at: row.get(8)?,
+            sha256: row.get(9)?,
+            local_path: row.get(10)?,
+            first_seen: row.get(11)?,
+            downloaded_at: row.get(12)?,
+            verified_at: row.get(13)?,
+            deleted_at: row.get(14)?,
+            failures: row.get(15)?,
+            last_error: row.get(16)?,
+            quarantined_at: row.get(17)?,
+            barcodes: row
+                .get::<_, Option<String>>(18)?
+                .and_then(|barcodes| serde_json::from_str(&barcodes).ok())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// filters for `Ledger::search`, `None` matches everything
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    pub account: Option<String>,
+    /// first day received, inclusive
+    pub from: Option<NaiveDate>,
+    /// last day received, inclusive
+    pub to: Option<NaiveDate>,
+    /// substring of the caller id
+    pub caller_id: Option<String>,
+    /// substring of the remote id
+    pub remote_id: Option<String>,
+    pub pages: Option<i64>,
+    /// substring of any barcode
+    pub barcode: Option<String>,
+}
+
+/// one destination's copy of a fax in one format
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredCopy {
+    pub destination: String,
+    pub format: String,
+    pub location: Option<String>,
+    pub sha256: Option<String>,
+    pub stored_at: Option<String>,
+    pub last_error: Option<String>,
+    /// location of the archive the copy was moved into by local retention
+    pub archive: Option<String>,
+    /// when local retention deleted the copy
+    pub purged_at: Option<String>,
+}
+impl StoredCopy {
+    const COLUMNS: &'static str =
+        "destination, format, location, sha256, stored_at, last_error, archive, purged_at";
+
+    fn from_row(row: &Row, offset: usize) -> rusqlite::Result<StoredCopy> {
+        Ok(StoredCopy {
+            destination: row.get(offset)?,
+            format: row.get(offset + 1)?,
+            location: row.get(offset + 2)?,
+            sha256: row.get(offset + 3)?,
+            stored_at: row.get(offset + 4)?,
+            last_error: row.get(offset + 5)?,
+            archive: row.get(offset + 6)?,
+            purged_at: row.get(offset + 7)?,
+        })
+    }
+
+    /// whether the copy is still a file of its own at its location
+    pub fn is_present(&self) -> bool {
+        self.stored_at.is_some() && self.archive.is_none() && self.purged_at.is_none()
+    }
+}
+
+/// record of every fax seen and downloaded, the source of truth for
+/// whether a fax still needs downloading
+pub struct Ledger {
+    conn: Connection,
+}
+
+impl Ledger {
+    pub fn open() -> Result<Ledger> {
+        let conn = Connection::open(&CONFIG.ledger)?;
+        // every polling thread opens its own connection
+        conn.busy_timeout(Duration::from_secs(30))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        // a verified download must survive a crash before the remote copy goes
+        conn.pragma_update(None, "synchronous", "FULL")?;
+
+        let ledger = Ledger { conn };
+        ledger.migrate()?;
+
+        Ok(ledger)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Ledger {
+        let ledger = Ledger {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        ledger.migrate().unwrap();
+        ledger
+    }
+
+    fn migrate(&self) -> Result<()> {
+        let version: usize = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            info!("migrating ledger to version {}", i + 1);
+            self.conn.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                i + 1
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// inserts the fax if it is new, keeping the time it was first seen
+    pub fn record_seen(&self, account: &str, item: &InboxItem) -> Result<()> {
+        let (_, details_id) = srfax::split_fax_filename(&item.FileName);
+
+        self.conn.execute(
+            "INSERT INTO faxes (account, filename, details_id, caller_id, remote_id, pages, size,
+                received, first_seen, received_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT (account, filename) DO UPDATE SET
+                caller_id = excluded.caller_id,
+                remote_id = excluded.remote_id,
+                pages = excluded.pages,
+                size = excluded.size,
+                received = excluded.received,
+                received_at = excluded.received_at",
+            params![
+                account,
+                item.FileName,
+                details_id.trim_start_matches('|'),
+                item.CallerID,
+                item.RemoteID,
+                item.Pages.trim().parse::<i64>().ok(),
+                item.Size.trim().parse::<i64>().ok(),
+                item.Date,
+                Local::now().to_rfc3339(),
+                item.received()
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// records a download that has been written and checked on disk
+    pub fn record_download(
+        &self,
+        account: &str,
+        filename: &str,
+        sha256: &str,
+        local_path: &str,
+    ) -> Result<()> {
+        let now = Local::now().to_rfc3339();
+
+        self.conn.execute(
+            "UPDATE faxes SET sha256 = ?3, local_path = ?4, downloaded_at = ?5, verified_at = ?5,
+                failures = 0, last_error = NULL
+            WHERE account = ?1 AND filename = ?2",
+            params![account, filename, sha256, local_path, now],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record_barcodes(
+        &self,
+        account: &str,
+        filename: &str,
+        barcodes: &[Barcode],
+    ) -> Result<()> {
+        let barcodes = serde_json::to_string(barcodes).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE faxes SET barcodes = ?3 WHERE account = ?1 AND filename = ?2",
+            params![account, filename, barcodes],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record_verified(&self, account: &str, filename: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE faxes SET verified_at = ?3 WHERE account = ?1 AND filename = ?2",
+            params![account, filename, Local::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record_deleted(&self, account: &str, filename: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE faxes SET deleted_at = ?3 WHERE account = ?1 AND filename = ?2",
+            params![account, filename, Local::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    /// counts a failed download, quarantining the fax once it has failed
+    /// `quarantine_after` times, returns true if it was just quarantined
+    pub fn record_failure(
+        &self,
+        account: &str,
+        item: &InboxItem,
+        error: &str,
+        quarantine_after: u32,
+    ) -> Result<bool> {
+        self.record_seen(account, item)?;
+
+        self.conn.execute(
+            "UPDATE faxes SET failures = failures + 1, last_error = ?3
+            WHERE account = ?1 AND filename = ?2",
+            params![account, item.FileName, error],
+        )?;
+        let quarantined = self.conn.execute(
+            "UPDATE faxes SET quarantined_at = ?4
+            WHERE account = ?1 AND filename = ?2 AND failures >= ?3 AND quarantined_at IS NULL",
+            params![
+                account,
+                item.FileName,
+                quarantine_after,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(quarantined > 0)
+    }
+
+    /// takes a fax out of quarantine so the next poll tries it again,
+    /// returns false if it wasn't quarantined
+    pub fn release(&self, account: &str, filename: &str) -> Result<bool> {
+        let released = self.conn.execute(
+            "UPDATE faxes SET quarantined_at = NULL, failures = 0
+            WHERE account = ?1 AND filename = ?2 AND quarantined_at IS NOT NULL",
+            params![account, filename],
+        )?;
+
+        Ok(released > 0)
+    }
+
+    /// downloaded faxes matching every filter that is set, oldest first
+    pub fn search(&self, search: &Search) -> Result<Vec<FaxRecord>> {
+        // faxes without a parseable srfax date fall back to when we first saw them
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM faxes
+            WHERE downloaded_at IS NOT NULL
+                AND (?1 IS NULL OR account = ?1)
+                AND (?2 IS NULL OR COALESCE(received_at, first_seen) >= ?2)
+                AND (?3 IS NULL OR COALESCE(received_at, first_seen) < ?3)
+                AND (?4 IS NULL OR caller_id LIKE '%' || ?4 || '%')
+                AND (?5 IS NULL OR remote_id LIKE '%' || ?5 || '%')
+                AND (?6 IS NULL OR pages = ?6)
+                AND (?7 IS NULL OR EXISTS (SELECT 1 FROM json_each(barcodes)
+                    WHERE json_extract(value, '$.data') LIKE '%' || ?7 || '%'))
+            ORDER BY COALESCE(received_at, first_seen)",
+            FaxRecord::COLUMNS
+        ))?;
+        let records = stmt
+            .query_map(
+                params![
+                    search.account,
+                    search.from.map(|d| d.format("%Y-%m-%d").to_string()),
+                    search
+                        .to
+                        .and_then(|d| d.succ_opt())
+                        .map(|d| d.format("%Y-%m-%d").to_string()),
+                    search.caller_id,
+                    search.remote_id,
+                    search.pages,
+                    search.barcode,
+                ],
+                FaxRecord::from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<FaxRecord>>>()?;
+
+        Ok(records)
+    }
+
+    pub fn quarantined(&self, account: Option<&str>) -> Result<Vec<FaxRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM faxes
+            WHERE quarantined_at IS NOT NULL AND (?1 IS NULL OR account = ?1)
+            ORDER BY account, quarantined_at",
+            FaxRecord::COLUMNS
+        ))?;
+        let records = stmt
+            .query_map(params![account], FaxRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<FaxRecord>>>()?;
+
+        Ok(records)
+    }
+
+    /// records a previous copy of a fax that was moved aside by a redownload
+    pub fn record_version(
+        &self,
+        account: &str,
+        filename: &str,
+        local_path: &str,
+        sha256: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO fax_versions (account, filename, local_path, sha256, replaced_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                account,
+                filename,
+                local_path,
+                sha256,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// whether the path is a previous copy kept by a redownload
+    pub fn is_version(&self, account: &str, local_path: &str) -> Result<bool> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM fax_versions WHERE account = ?1 AND local_path = ?2",
+                params![account, local_path],
+                |_| Ok(()),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    /// forgets the download so the fax is fetched again on the next poll
+    pub fn requeue(&self, account: &str, filename: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE faxes SET sha256 = NULL, local_path = NULL, downloaded_at = NULL,
+                verified_at = NULL
+            WHERE account = ?1 AND filename = ?2",
+            params![account, filename],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_by_local_path(&self, account: &str, local_path: &str) -> Result<Option<FaxRecord>> {
+        let record = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM faxes WHERE account = ?1 AND local_path = ?2",
+                    FaxRecord::COLUMNS
+                ),
+                params![account, local_path],
+                FaxRecord::from_row,
+            )
+            .optional()?;
+
+        Ok(record)
+    }
+
+    /// the fax stored at a location, as its primary copy or any other copy
+    /// at any destination
+    pub fn find_by_location(&self, account: &str, location: &str) -> Result<Option<FaxRecord>> {
+        let record = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM faxes WHERE account = ?1 AND (local_path = ?2
+                        OR filename IN (SELECT filename FROM fax_destinations
+                            WHERE account = ?1 AND location = ?2))",
+                    FaxRecord::COLUMNS
+                ),
+                params![account, location],
+                FaxRecord::from_row,
+            )
+            .optional()?;
+
+        Ok(record)
+    }
+
+    pub fn get(&self, account: &str, filename: &str) -> Result<Option<FaxRecord>> {
+        let record = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM faxes WHERE account = ?1 AND filename = ?2",
+                    FaxRecord::COLUMNS
+                ),
+                params![account, filename],
+                FaxRecord::from_row,
+            )
+            .optional()?;
+
+        Ok(record)
+    }
+
+    /// records that a destination has a verified copy of the fax
+    pub fn record_stored(
+        &self,
+        account: &str,
+        filename: &str,
+        destination: &str,
+        format: &str,
+        location: &str,
+        sha256: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO fax_destinations
+                (account, filename, destination, format, location, sha256, stored_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT (account, filename, destination, format) DO UPDATE SET
+                location = excluded.location,
+                sha256 = excluded.sha256,
+                stored_at = excluded.stored_at,
+                last_error = NULL",
+            params![
+                account,
+                filename,
+                destination,
+                format,
+                location,
+                sha256,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// records why a destination failed, keeping any earlier copy
+    pub fn record_store_failure(
+        &self,
+        account: &str,
+        filename: &str,
+        destination: &str,
+        format: &str,
+        error: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO fax_destinations (account, filename, destination, format, last_error)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (account, filename, destination, format) DO UPDATE SET
+                last_error = excluded.last_error",
+            params![account, filename, destination, format, error],
+        )?;
+
+        Ok(())
+    }
+
+    /// forgets a destination's copy at `location`, returns the fax it was for
+    pub fn forget_stored(
+        &self,
+        account: &str,
+        destination: &str,
+        location: &str,
+    ) -> Result<Option<String>> {
+        let filename = self
+            .conn
+            .query_row(
+                "UPDATE fax_destinations SET stored_at = NULL
+                WHERE account = ?1 AND destination = ?2 AND location = ?3
+                RETURNING filename",
+                params![account, destination, location],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(filename)
+    }
+
+    pub fn stored_copies(&self, account: &str, filename: &str) -> Result<Vec<StoredCopy>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM fax_destinations WHERE account = ?1 AND filename = ?2
+            ORDER BY destination, format",
+            StoredCopy::COLUMNS
+        ))?;
+        let copies = stmt
+            .query_map(params![account, filename], |row| {
+                StoredCopy::from_row(row, 0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(copies)
+    }
+
+    /// every copy a destination still has as a file of its own, as (fax
+    /// filename, copy)
+    pub fn copies_at(&self, account: &str, destination: &str) -> Result<Vec<(String, StoredCopy)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT filename, {} FROM fax_destinations
+            WHERE account = ?1 AND destination = ?2 AND stored_at IS NOT NULL
+                AND archive IS NULL AND purged_at IS NULL
+            ORDER BY filename, format",
+            StoredCopy::COLUMNS
+        ))?;
+        let copies = stmt
+            .query_map(params![account, destination], |row| {
+                Ok((row.get(0)?, StoredCopy::from_row(row, 1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(copies)
+    }
+
+    /// every copy a destination moved into the archive at `archive`, as (fax
+    /// filename, copy)
+    pub fn archived_in(
+        &self,
+        account: &str,
+        destination: &str,
+        archive: &str,
+    ) -> Result<Vec<(String, StoredCopy)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT filename, {} FROM fax_destinations
+            WHERE account = ?1 AND destination = ?2 AND archive = ?3 AND purged_at IS NULL
+            ORDER BY filename, format",
+            StoredCopy::COLUMNS
+        ))?;
+        let copies = stmt
+            .query_map(params![account, destination, archive], |row| {
+                Ok((row.get(0)?, StoredCopy::from_row(row, 1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(copies)
+    }
+
+    /// records that a copy was moved into the archive at `archive`
+    pub fn record_archived(
+        &self,
+        account: &str,
+        filename: &str,
+        destination: &str,
+        format: &str,
+        archive: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE fax_destinations SET archive = ?5, archived_at = ?6
+            WHERE account = ?1 AND filename = ?2 AND destination = ?3 AND format = ?4",
+            params![
+                account,
+                filename,
+                destination,
+                format,
+                archive,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// records that a copy, archived or not, was deleted by local retention
+    pub fn record_purged(
+        &self,
+        account: &str,
+        filename: &str,
+        destination: &str,
+        format: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE fax_destinations SET purged_at = ?5
+            WHERE account = ?1 AND filename = ?2 AND destination = ?3 AND format = ?4",
+            params![
+                account,
+                filename,
+                destination,
+                format,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// records an archive written by local retention, or its new checksum
+    pub fn record_archive(
+        &self,
+        account: &str,
+        destination: &str,
+        location: &str,
+        sha256: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO archives (account, destination, location, sha256, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (account, destination, location) DO UPDATE SET
+                sha256 = excluded.sha256,
+                updated_at = excluded.updated_at",
+            params![
+                account,
+                destination,
+                location,
+                sha256,
+                Local::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn forget_archive(&self, account: &str, destination: &str, location: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM archives WHERE account = ?1 AND destination = ?2 AND location = ?3",
+            params![account, destination, location],
+        )?;
+
+        Ok(())
+    }
+
+    /// every archive of a destination, as (location, sha256)
+    pub fn archives(&self, account: &str, destination: &str) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT location, sha256 FROM archives WHERE account = ?1 AND destination = ?2
+            ORDER BY location",
+        )?;
+        let archives = stmt
+            .query_map(params![account, destination], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(archives)
+    }
+
+    pub fn is_archive(&self, account: &str, location: &str) -> Result<bool> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM archives WHERE account = ?1 AND location = ?2",
+                params![account, location],
+                |_| Ok(()),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    /// downloaded faxes still stored somewhere that haven't been through ocr
+    /// and failed it fewer than `max_failures` times, newest first
+    pub fn pending_ocr(&self, account: &str, max_failures: u32) -> Result<Vec<FaxRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM faxes
+            WHERE account = ?1 AND downloaded_at IS NOT NULL AND ocr_at IS NULL
+                AND ocr_failures < ?2
+                AND EXISTS (SELECT 1 FROM fax_destinations d
+                    WHERE d.account = faxes.account AND d.filename = faxes.filename
+                        AND d.stored_at IS NOT NULL AND d.archive IS NULL
+                        AND d.purged_at IS NULL)
+            ORDER BY downloaded_at DESC",
+            FaxRecord::COLUMNS
+        ))?;
+        let records = stmt
+            .query_map(params![account, max_failures], FaxRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<FaxRecord>>>()?;
+
+        Ok(records)
+    }
+
+    pub fn record_ocr(&self, account: &str, filename: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE faxes SET ocr_at = ?3, ocr_failures = 0, ocr_error = NULL
+            WHERE account = ?1 AND filename = ?2",
+            params![account, filename, Local::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    /// counts a failed ocr, returns how often the fax has failed it
+    pub fn record_ocr_failure(&self, account: &str, filename: &str, error: &str) -> Result<u32> {
+        let failures = self.conn.query_row(
+            "UPDATE faxes SET ocr_failures = ocr_failures + 1, ocr_error = ?3
+            WHERE account = ?1 AND filename = ?2
+            RETURNING ocr_failures",
+            params![account, filename, error],
+            |row| row.get(0),
+        )?;
+
+        Ok(failures)
+    }
+
+    /// queues the fax for ocr again, like after it was downloaded again
+    pub fn requeue_ocr(&self, account: &str, filename: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE faxes SET ocr_at = NULL, ocr_failures = 0, ocr_error = NULL
+            WHERE account = ?1 AND filename = ?2",
+            params![account, filename],
+        )?;
+
+        Ok(())
+    }
+
+    /// gives faxes that failed ocr another try, returns how many
+    pub fn retry_failed_ocr(&self, account: &str) -> Result<usize> {
+        let retried = self.conn.execute(
+            "UPDATE faxes SET ocr_failures = 0
+            WHERE account = ?1 AND ocr_at IS NULL AND ocr_failures > 0",
+            params![account],
+        )?;
+
+        Ok(retried)
+    }
+
+    /// every previous copy kept by a redownload, as (location, sha256)
+    pub fn versions(&self, account: &str) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT local_path, sha256 FROM fax_versions WHERE account = ?1
+            ORDER BY replaced_at",
+        )?;
+        let versions = stmt
+            .query_map(params![account], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(versions)
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(ledger: &Ledger) -> usize {
+        ledger
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    fn item(filename: &str) -> InboxItem {
+        InboxItem {
+            FileName: filename.to_string(),
+            ReceiveStatus: "Ok".to_string(),
+            Date: "Oct 19/2026 09:15 AM".to_string(),
+            CallerID: "4165551234".to_string(),
+            RemoteID: "Dr Smith".to_string(),
+            Pages: "2".to_string(),
+            Size: "426".to_string(),
+        }
+    }
+
+    #[test]
+    fn migrates_to_latest() {
+        let ledger = Ledger::in_memory();
+        assert_eq!(version(&ledger), MIGRATIONS.len());
+
+        // migrating again changes nothing
+        ledger.migrate().unwrap();
+        assert_eq!(version(&ledger), MIGRATIONS.len());
+
+        let account = "clinic";
+        let filename = "20261019120000-1234-5_1|98765";
+        ledger.record_seen(account, &item(filename)).unwrap();
+        ledger
+            .record_download(account, filename, "abc", "faxes/a.pdf")
+            .unwrap();
+        ledger
+            .record_stored(account, filename, "nas", "PDF", "faxes/a.pdf", "abc")
+            .unwrap();
+        ledger
+            .record_barcodes(
+                account,
+                filename,
+                &[Barcode {
+                    symbology: "CODE-128".to_string(),
+                    data: "PT12345".to_string(),
+                    page: 1,
+                }],
+            )
+            .unwrap();
+
+        let record = ledger.get(account, filename).unwrap().unwrap();
+        assert_eq!(record.local_path.as_deref(), Some("faxes/a.pdf"));
+        assert_eq!(record.received_at.as_deref(), Some("2026-10-19 09:15:00"));
+        assert_eq!(record.barcodes[0].data, "PT12345");
+        let copies = ledger.stored_copies(account, filename).unwrap();
+        assert_eq!(copies.len(), 1);
+        assert!(copies[0].is_present());
+
+        let search = Search {
+            barcode: Some("T123".to_string()),
+            ..Search::default()
+        };
+        assert_eq!(ledger.search(&search).unwrap().len(), 1);
+        let search = Search {
+            barcode: Some("CASE".to_string()),
+            ..Search::default()
+        };
+        assert!(ledger.search(&search).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrates_existing_downloads() {
+        let ledger = Ledger {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        // a ledger from before destinations, with one downloaded fax
+        ledger
+            .conn
+            .execute_batch(&format!(
+                "{} {} PRAGMA user_version = 2;
+                INSERT INTO faxes (account, filename, details_id, caller_id, remote_id, pages,
+                    size, received, sha256, local_path, first_seen, downloaded_at)
+                VALUES ('clinic', 'a|1', '1', '', '', 2, 426, 'Oct 19/2026 09:15 AM', 'abc',
+                    'faxes/a.tif', '2026-10-19T09:20:00', '2026-10-19T09:20:00');",
+                MIGRATIONS[0], MIGRATIONS[1]
+            ))
+            .unwrap();
+
+        ledger.migrate().unwrap();
+        assert_eq!(version(&ledger), MIGRATIONS.len());
+
+        let copies = ledger.stored_copies("clinic", "a|1").unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].destination, "default");
+        assert_eq!(copies[0].format, "TIF");
+        assert_eq!(copies[0].location.as_deref(), Some("faxes/a.tif"));
+        assert!(copies[0].is_present());
+
+        let record = ledger.get("clinic", "a|1").unwrap().unwrap();
+        assert_eq!(record.failures, 0);
+        assert!(record.barcodes.is_empty());
+    }
+
+    #[test]
+    fn finds_every_copy_by_location() {
+        let ledger = Ledger::in_memory();
+        let filename = "20261019120000-1234-5_1|98765";
+        ledger.record_seen("clinic", &item(filename)).unwrap();
+        ledger
+            .record_download("clinic", filename, "abc", "faxes/a.PDF")
+            .unwrap();
+        ledger
+            .record_stored("clinic", filename, "nas", "TIF", "faxes/a.TIF", "def")
+            .unwrap();
+        ledger
+            .record_stored("clinic", filename, "mirror", "PDF", "s3://b/a.PDF", "abc")
+            .unwrap();
+
+        for location in ["faxes/a.PDF", "faxes/a.TIF", "s3://b/a.PDF"] {
+            let record = ledger.find_by_location("clinic", location).unwrap();
+            assert_eq!(
+                record.map(|record| record.filename).as_deref(),
+                Some(filename)
+            );
+        }
+        assert!(ledger
+            .find_by_location("clinic", "faxes/b.PDF")
+            .unwrap()
+            .is_none());
+        assert!(ledger
+            .find_by_location("other", "faxes/a.TIF")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn quarantines_after_repeated_failures() {
+        let ledger = Ledger::in_memory();
+        let filename = "20261019120000-1234-5_1|98765";
+        let fax = item(filename);
+
+        for _ in 0..2 {
+            assert!(!ledger
+                .record_failure("clinic", &fax, "timed out", 3)
+                .unwrap());
+        }
+        let record = ledger.get("clinic", filename).unwrap().unwrap();
+        assert_eq!(record.failures, 2);
+        assert!(record.quarantined_at.is_none());
+
+        // quarantined once, at the limit
+        assert!(ledger
+            .record_failure("clinic", &fax, "timed out", 3)
+            .unwrap());
+        assert!(!ledger
+            .record_failure("clinic", &fax, "timed out", 3)
+            .unwrap());
+        // polling skips faxes with quarantined_at set
+        let record = ledger.get("clinic", filename).unwrap().unwrap();
+        assert_eq!(record.failures, 4);
+        assert!(record.quarantined_at.is_some());
+        assert_eq!(record.last_error.as_deref(), Some("timed out"));
+        assert_eq!(ledger.quarantined(Some("clinic")).unwrap().len(), 1);
+        assert!(ledger.quarantined(Some("other")).unwrap().is_empty());
+
+        assert!(ledger.release("clinic", filename).unwrap());
+        assert!(!ledger.release("clinic", filename).unwrap());
+        let record = ledger.get("clinic", filename).unwrap().unwrap();
+        assert_eq!(record.failures, 0);
+        assert!(record.quarantined_at.is_none());
+        assert!(ledger.quarantined(None).unwrap().is_empty());
+    }
+}
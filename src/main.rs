@@ -23,13 +23,31 @@ extern crate thiserror;
 #[macro_use]
 extern crate windows_service;
 
+mod archive;
+mod barcode;
 mod cli;
 mod common;
 mod config;
+mod control;
+mod convert;
+mod disk_space;
+mod document;
 mod email;
+mod encryption;
+mod history;
+mod ledger;
+mod metadata;
+mod ocr;
+mod quarantine;
+mod reconcile;
 mod response;
+mod retention;
 mod srfax;
 mod srfax_service;
+mod state;
+mod storage;
+mod template;
+mod verify;
 
 #[cfg(windows)]
 mod main_ws;
@@ -40,6 +58,13 @@ use config::CONFIG;
 use std::time;
 
 pub const EXIT_CODE_0: i32 = 0;
+// `run --once` exit codes, or'd together when several kinds of failure happen
+pub const EXIT_CODE_ERROR: i32 = 1;
+pub const EXIT_CODE_NETWORK: i32 = 2;
+pub const EXIT_CODE_AUTH: i32 = 4;
+pub const EXIT_CODE_DOWNLOAD: i32 = 8;
+pub const EXIT_CODE_DELETE: i32 = 16;
+pub const EXIT_CODE_LOW_SPACE: i32 = 32;
 
 #[cfg(windows)]
 const SERVICE_NAME: &str = "SRFax";
@@ -72,6 +97,8 @@ pub fn run_program() -> Result<()> {
     let tick_time = time::Duration::from_secs(CONFIG.tick_rate);
 
     setup_logging()?;
+    let _lock = srfax_service::recover_partial_downloads(&config::get_srfaxes()?)?;
+    srfax_service::check_permissions()?;
 
     // start service
     srfax_service::run_srfax_service(tick_time);
@@ -80,6 +107,71 @@ pub fn run_program() -> Result<()> {
     Ok(())
 }
 
+/// polls the given accounts (or all of them) once and returns the exit code
+pub fn run_once(accounts: &[String]) -> Result<i32> {
+    setup_logging()?;
+
+    let mut srfaxes = config::get_srfaxes()?;
+    for name in accounts {
+        if !srfaxes.iter().any(|srfax| &srfax.name == name) {
+            anyhow::bail!("no srfax named {:?}", name);
+        }
+    }
+    if !accounts.is_empty() {
+        srfaxes.retain(|srfax| accounts.contains(&srfax.name));
+    }
+
+    let _lock = srfax_service::recover_partial_downloads(&srfaxes)?;
+    srfax_service::check_permissions()?;
+    let ocr_accounts = srfaxes.clone();
+    let results = srfax_service::run_srfax_once(srfaxes);
+    email::wait_for_pending();
+
+    let mut code = EXIT_CODE_0;
+    for (name, result) in results {
+        match result {
+            Ok(report) => {
+                println!(
+                    "{}: downloaded={} skipped={} quarantined={} deleted={} download_errors={} \
+                    delete_errors={} low_space={}",
+                    name,
+                    report.downloaded,
+                    report.skipped,
+                    report.quarantined,
+                    report.deleted,
+                    report.download_errors,
+                    report.delete_errors,
+                    report.low_space
+                );
+                if report.download_errors > 0 {
+                    code |= EXIT_CODE_DOWNLOAD;
+                }
+                if report.delete_errors > 0 {
+                    code |= EXIT_CODE_DELETE;
+                }
+                if report.low_space {
+                    code |= EXIT_CODE_LOW_SPACE;
+                }
+            }
+            Err(e) => {
+                println!("{}: failed, {}", name, e);
+                code |= match e {
+                    _ if e.is_network() => EXIT_CODE_NETWORK,
+                    srfax_service::Error::AuthFailed(_) => EXIT_CODE_AUTH,
+                    _ => EXIT_CODE_ERROR,
+                };
+            }
+        }
+    }
+
+    // after the summary, so it doesn't hold up the downloads
+    ocr::run_pending(&ocr_accounts, &mut Default::default());
+    email::wait_for_pending();
+
+    info!("done, exit code {}", code);
+    Ok(code)
+}
+
 fn setup_logging() -> Result<()> {
     use flexi_logger::{opt_format, Duplicate, FileSpec, Logger};
 
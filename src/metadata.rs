@@ -0,0 +1,263 @@
+use crate::{
+    barcode::Barcode,
+    config::Srfax,
+    encryption::{self, EncryptionError, ENCRYPTED_SUFFIX},
+    response::{DownloadFormat, InboxItem},
+    srfax::{self, split_fax_filename},
+    storage::{self, Storage, StorageError},
+    template,
+};
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error("Metadata(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Metadata(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Metadata(Encryption({0:?}))")]
+    Encryption(#[from] EncryptionError),
+
+    #[error("Metadata(Json({0:?}))")]
+    Json(#[from] serde_json::Error),
+}
+type Result<T> = std::result::Result<T, MetadataError>;
+
+/// bumped whenever a field is renamed or removed, new fields don't change it
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFormat {
+    #[default]
+    Json,
+    Xml,
+}
+impl MetadataFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MetadataFormat::Json => "json",
+            MetadataFormat::Xml => "xml",
+        }
+    }
+}
+
+/// everything srfax told us about a fax, and where it was saved
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub schema: u32,
+    pub account: String,
+    /// the srfax filename, including the `|details id`
+    pub srfax_filename: String,
+    pub details_id: String,
+    /// the name of the saved fax
+    pub file: String,
+    /// where the fax was saved, a path or a url for remote storage
+    pub path: String,
+    pub format: String,
+    pub caller_id: String,
+    pub remote_id: String,
+    /// `YYYY-MM-DDTHH:MM:SS` as srfax reported it, if the date could be read
+    pub received: Option<String>,
+    /// `Date` exactly as srfax sent it
+    pub received_raw: String,
+    pub pages: Option<u32>,
+    pub size: Option<u64>,
+    pub sha256: String,
+    pub downloaded_at: DateTime<Local>,
+    /// empty unless `barcodes` is set
+    pub barcodes: Vec<Barcode>,
+}
+
+impl Metadata {
+    pub fn new(
+        srfax: &Srfax,
+        item: &InboxItem,
+        format: &DownloadFormat,
+        key: &str,
+        location: &str,
+        sha256: &str,
+        barcodes: &[Barcode],
+    ) -> Metadata {
+        let (_filename, details_id) = split_fax_filename(&item.FileName);
+
+        Metadata {
+            schema: SCHEMA_VERSION,
+            account: srfax.name.clone(),
+            srfax_filename: item.FileName.clone(),
+            details_id: details_id.trim_start_matches('|').to_string(),
+            file: key.rsplit('/').next().unwrap_or_default().to_string(),
+            path: location.to_string(),
+            format: format.to_string(),
+            caller_id: item.CallerID.trim().to_string(),
+            remote_id: item.RemoteID.trim().to_string(),
+            received: item
+                .received()
+                .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string()),
+            received_raw: item.Date.clone(),
+            pages: item.Pages.trim().parse().ok(),
+            size: item.Size.trim().parse().ok(),
+            sha256: sha256.to_string(),
+            downloaded_at: Local::now(),
+            barcodes: barcodes.to_vec(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// `<fax>` with one element per field, missing values are left out, and a
+    /// `<barcode>` per barcode
+    pub fn to_xml(&self) -> String {
+        let fields: &[(&str, Option<String>)] = &[
+            ("schema", Some(self.schema.to_string())),
+            ("account", Some(self.account.clone())),
+            ("srfax_filename", Some(self.srfax_filename.clone())),
+            ("details_id", Some(self.details_id.clone())),
+            ("file", Some(self.file.clone())),
+            ("path", Some(self.path.clone())),
+            ("format", Some(self.format.clone())),
+            ("caller_id", Some(self.caller_id.clone())),
+            ("remote_id", Some(self.remote_id.clone())),
+            ("received", self.received.clone()),
+            ("received_raw", Some(self.received_raw.clone())),
+            ("pages", self.pages.map(|v| v.to_string())),
+            ("size", self.size.map(|v| v.to_string())),
+            ("sha256", Some(self.sha256.clone())),
+            ("downloaded_at", Some(self.downloaded_at.to_rfc3339())),
+        ];
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<fax>\n");
+        for (name, v) in fields {
+            if let Some(v) = v {
+                let _ = writeln!(xml, "  <{0}>{1}</{0}>", name, xml_escape(v));
+            }
+        }
+        for barcode in &self.barcodes {
+            let _ = writeln!(
+                xml,
+                "  <barcode type=\"{}\" page=\"{}\">{}</barcode>",
+                xml_escape(&barcode.symbology),
+                barcode.page,
+                xml_escape(&barcode.data)
+            );
+        }
+        xml.push_str("</fax>\n");
+
+        xml
+    }
+
+    fn render(&self, format: MetadataFormat) -> Result<String> {
+        match format {
+            MetadataFormat::Json => self.to_json(),
+            MetadataFormat::Xml => Ok(self.to_xml()),
+        }
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // not allowed in xml 1.0 at all
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => (),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `<fax without extension>.json` next to the fax, or `<fax>.json` when
+/// every fax is downloaded in several formats so they don't share one.
+/// encrypted sidecars end in `.age` like the fax
+pub fn sidecar_key(srfax: &Srfax, fax: &str) -> String {
+    let ext = srfax.metadata_format.extension();
+    let suffix = encryption::suffix(srfax);
+    let fax = fax.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(fax);
+    if srfax.formats().len() > 1 {
+        format!("{}.{}{}", fax, ext, suffix)
+    } else {
+        format!("{}.{}{}", srfax::split_ext(fax).0, ext, suffix)
+    }
+}
+
+/// `<manifest_dir>/<account>_<srfax filename>.json`, with `_<format>` before
+/// the extension when there are several formats
+pub fn manifest_path(
+    dir: &str,
+    srfax: &Srfax,
+    item: &InboxItem,
+    format: &DownloadFormat,
+) -> PathBuf {
+    let (filename, _details_id) = split_fax_filename(&item.FileName);
+    let mut name = format!("{}_{}", srfax.name, filename);
+    if srfax.formats().len() > 1 {
+        name = format!("{}_{}", name, format);
+    }
+
+    Path::new(dir).join(format!(
+        "{}.{}{}",
+        template::sanitize(&name),
+        srfax.metadata_format.extension(),
+        encryption::suffix(srfax)
+    ))
+}
+
+/// whether `key` looks like one of our sidecars rather than a fax
+pub fn is_sidecar(srfax: &Srfax, key: &str) -> bool {
+    let ext = srfax::split_ext(key).1;
+    let ext = ext.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(ext);
+    srfax.sidecar && ext == format!(".{}", srfax.metadata_format.extension())
+}
+
+/// writes the sidecar next to the fax at `key`, if configured
+pub fn write_sidecar(
+    srfax: &Srfax,
+    storage: &dyn Storage,
+    key: &str,
+    metadata: &Metadata,
+) -> Result<()> {
+    if !srfax.sidecar {
+        return Ok(());
+    }
+
+    let content = encryption::seal(srfax, metadata.render(srfax.metadata_format)?.as_bytes())?;
+    storage.put(&sidecar_key(srfax, key), &content)?;
+
+    Ok(())
+}
+
+/// writes the manifest file, if configured. there's one per fax and format
+/// no matter how many destinations it was stored at
+pub fn write_manifest(
+    srfax: &Srfax,
+    metadata: &Metadata,
+    item: &InboxItem,
+    format: &DownloadFormat,
+) -> Result<()> {
+    let dir = match srfax.manifest_dir {
+        Some(ref dir) => dir,
+        None => return Ok(()),
+    };
+
+    let content = encryption::seal(srfax, metadata.render(srfax.metadata_format)?.as_bytes())?;
+    let permissions = storage::Permissions::new(srfax)?;
+    permissions.create_dir_all(Path::new(dir))?;
+    storage::write_to_file(
+        &content,
+        &manifest_path(dir, srfax, item, format),
+        &permissions,
+    )?;
+
+    Ok(())
+}
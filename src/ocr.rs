@@ -0,0 +1,337 @@
+use crate::{
+    common,
+    config::{self, ConfigError, Srfax, CONFIG},
+    convert::{self, ConvertError},
+    email,
+    ledger::{self, FaxRecord, Ledger, LedgerError},
+    response::DownloadFormat,
+    srfax,
+    storage::{self, Destination, StorageError},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Error)]
+pub enum OcrError {
+    #[error("Ocr(Config({0:?}))")]
+    Config(#[from] ConfigError),
+
+    #[error("Ocr(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Ocr(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Ocr(Convert({0:?}))")]
+    Convert(#[from] ConvertError),
+
+    #[error("Ocr(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Ocr(tesseract not found at {0:?})")]
+    NotInstalled(String),
+
+    #[error("Ocr(tesseract failed: {0})")]
+    Tesseract(String),
+
+    #[error("Ocr({0} is encrypted, ocr needs the faxes in the clear)")]
+    Encrypted(String),
+
+    #[error("Ocr(no stored copy of {0})")]
+    NotStored(String),
+
+    #[error("Ocr({0} changed since download)")]
+    Changed(String),
+
+    #[error("Ocr(no srfax named {0:?})")]
+    UnknownAccount(String),
+}
+type Result<T> = std::result::Result<T, OcrError>;
+
+/// a fax is given up on after failing this many times, until `ocr --retry`
+const MAX_FAILURES: u32 = 3;
+
+/// ledger formats of the searchable pdf and the text next to a fax
+pub const OCR_PDF: &str = "OCR_PDF";
+pub const OCR_TEXT: &str = "OCR_TXT";
+
+/// what `run_account` did
+#[derive(Debug, Default)]
+pub struct OcrReport {
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// runs ocr in the background every `interval`, apart from polling so
+/// downloads never wait for it
+pub fn start_worker(interval: Duration) {
+    thread::spawn(move || {
+        let mut reported = HashSet::new();
+        loop {
+            match config::get_srfaxes() {
+                Ok(srfaxes) => run_pending(&srfaxes, &mut reported),
+                Err(e) => warn!("could not read srfaxes for ocr! {:?}", e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// runs ocr for every account that has it turned on. an account's error is
+/// only reported until it changes, `reported` keeps track across runs
+pub fn run_pending(srfaxes: &[Srfax], reported: &mut HashSet<String>) {
+    let ledger = match Ledger::open() {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            warn!("could not open the ledger for ocr! {:?}", e);
+            return;
+        }
+    };
+
+    for srfax in srfaxes.iter().filter(|srfax| srfax.ocr) {
+        match run_account(srfax, &ledger) {
+            Ok(report) => {
+                reported.remove(&srfax.name);
+                if report.done > 0 || report.failed > 0 {
+                    info!(
+                        "ocr done! name={} done={} failed={}",
+                        srfax.name, report.done, report.failed
+                    );
+                }
+            }
+            Err(e) => {
+                if reported.insert(srfax.name.clone()) {
+                    warn!("error running ocr! name={} error={:?}", srfax.name, e);
+                    email::send_email_fork(
+                        "error running ocr",
+                        &format!("error running ocr! name={} error={}", srfax.name, e),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// runs ocr on the faxes of an account that are waiting for it
+pub fn run_account(srfax: &Srfax, ledger: &Ledger) -> Result<OcrReport> {
+    let mut report = OcrReport::default();
+
+    // the private key isn't here to read them
+    if !srfax.encrypt_to.is_empty() {
+        return Err(OcrError::Encrypted(srfax.name.clone()));
+    }
+    let pending = ledger.pending_ocr(&srfax.name, MAX_FAILURES)?;
+    if pending.is_empty() {
+        return Ok(report);
+    }
+    let destinations = storage::open_destinations(srfax)?;
+
+    for record in pending {
+        match ocr_fax(srfax, ledger, &destinations, &record) {
+            Ok(location) => {
+                debug!("ocr of {} written to {}", record.filename, location);
+                report.done += 1;
+            }
+            // not the fax's fault, try again next time
+            Err(e @ OcrError::NotInstalled(_)) => return Err(e),
+            Err(e) => {
+                report.failed += 1;
+                let failures =
+                    ledger.record_ocr_failure(&srfax.name, &record.filename, &e.to_string())?;
+                warn!(
+                    "error running ocr on fax! name={} FileName=[{}] failures={} error={:?}",
+                    srfax.name, record.filename, failures, e
+                );
+                if failures >= MAX_FAILURES {
+                    email::send_email_fork(
+                        "ocr failed",
+                        &format!(
+                            "ocr failed {} times and won't be tried again until `ocr --retry`. \
+                            name={} FileName=[{}] error={}",
+                            failures, srfax.name, record.filename, e
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// writes the searchable pdf and text of a fax next to it at every
+/// destination, returns where the pdf went at the first
+fn ocr_fax(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+    record: &FaxRecord,
+) -> Result<String> {
+    let primary = destinations
+        .first()
+        .ok_or_else(|| StorageError::Config("no destinations".to_string()))?;
+    let storage = primary.storage()?;
+
+    // a tiff goes to tesseract as it is, a pdf has to be converted
+    let copies = ledger.stored_copies(&srfax.name, &record.filename)?;
+    let source = [DownloadFormat::TIF, DownloadFormat::PDF]
+        .into_iter()
+        .find_map(|format| {
+            let copy = copies.iter().find(|copy| {
+                copy.destination == primary.name
+                    && copy.format == format.to_string()
+                    && copy.is_present()
+            })?;
+            let key = copy.location.as_deref().and_then(|l| storage.key(l))?;
+            Some((format, key, copy.sha256.clone()))
+        });
+    let (format, key, sha256) =
+        source.ok_or_else(|| OcrError::NotStored(record.filename.clone()))?;
+
+    let data = storage.get(&key)?;
+    if sha256.as_deref() != Some(ledger::sha256_hex(&data).as_str()) {
+        return Err(OcrError::Changed(storage.location(&key)));
+    }
+    let (pdf, text) = tesseract(srfax, &convert::square_tiff(&data, &format, None)?)?;
+
+    let base = srfax::split_ext(&key).0;
+    let outputs = [
+        (OCR_PDF, format!("{}.ocr.pdf", base), pdf),
+        (OCR_TEXT, format!("{}.ocr.txt", base), text),
+    ];
+    for (format, key, data) in &outputs {
+        let mut failed = None;
+        for destination in destinations {
+            let stored = destination.storage().and_then(|storage| {
+                storage.put(key, data)?;
+                Ok(storage.location(key))
+            });
+
+            match stored {
+                Ok(location) => ledger.record_stored(
+                    &srfax.name,
+                    &record.filename,
+                    &destination.name,
+                    format,
+                    &location,
+                    &ledger::sha256_hex(data),
+                )?,
+                Err(e) => {
+                    ledger.record_store_failure(
+                        &srfax.name,
+                        &record.filename,
+                        &destination.name,
+                        format,
+                        &e.to_string(),
+                    )?;
+                    if destination.required {
+                        failed.get_or_insert(e);
+                    } else {
+                        warn!(
+                            "error storing {} at optional destination {}, continuing. {:?}",
+                            key, destination.name, e
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e.into());
+        }
+    }
+    ledger.record_ocr(&srfax.name, &record.filename)?;
+
+    Ok(storage.location(&outputs[0].1))
+}
+
+/// runs tesseract on a tiff in a private temporary directory, returns the
+/// searchable pdf and the text
+fn tesseract(srfax: &Srfax, image: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let dir = common::private_temp_dir("srfax-ocr")?;
+    let result = run_tesseract(srfax, &dir, image);
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("could not remove {:?}! {:?}", dir, e);
+    }
+    result
+}
+
+fn run_tesseract(srfax: &Srfax, dir: &Path, image: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let input = dir.join("fax.tif");
+    fs::write(&input, image)?;
+
+    // `pdf` and `txt` write ocr.pdf and ocr.txt in one pass
+    let output = std::process::Command::new(&CONFIG.tesseract)
+        .arg(&input)
+        .arg(dir.join("ocr"))
+        .args(["-l", &srfax.ocr_language])
+        .args(["pdf", "txt"])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => OcrError::NotInstalled(CONFIG.tesseract.clone()),
+            _ => e.into(),
+        })?;
+    if !output.status.success() {
+        return Err(OcrError::Tesseract(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok((
+        fs::read(dir.join("ocr.pdf"))?,
+        fs::read(dir.join("ocr.txt"))?,
+    ))
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("ocr")
+            .about("run ocr now on faxes that are waiting for it")
+            .arg(Arg::new("account").long("account"))
+            .arg(
+                Arg::new("retry")
+                    .long("retry")
+                    .action(ArgAction::SetTrue)
+                    .help("also try faxes that failed ocr too many times"),
+            ),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("ocr") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+    let account = matches.get_one::<String>("account");
+
+    let srfaxes: Vec<Srfax> = config::get_srfaxes()?
+        .into_iter()
+        .filter(|srfax| account.is_none_or(|name| *name == srfax.name))
+        .collect();
+    if let (Some(name), true) = (account, srfaxes.is_empty()) {
+        return Err(OcrError::UnknownAccount(name.clone()));
+    }
+
+    let ledger = Ledger::open()?;
+    for srfax in &srfaxes {
+        if !srfax.ocr {
+            println!("{}: ocr is off", srfax.name);
+            continue;
+        }
+        if matches.get_flag("retry") {
+            let retried = ledger.retry_failed_ocr(&srfax.name)?;
+            println!("{}: retrying {} failed faxes", srfax.name, retried);
+        }
+
+        let report = run_account(srfax, &ledger)?;
+        println!(
+            "{}: done={} failed={}",
+            srfax.name, report.done, report.failed
+        );
+    }
+
+    Ok(true)
+}
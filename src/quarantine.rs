@@ -0,0 +1,69 @@
+use crate::ledger::{Ledger, LedgerError};
+use clap::{Arg, ArgMatches, Command};
+
+#[derive(Debug, Error)]
+pub enum QuarantineError {
+    #[error("Quarantine(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Quarantine({0} is not quarantined)")]
+    NotQuarantined(String),
+}
+type Result<T> = std::result::Result<T, QuarantineError>;
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("quarantine")
+            .about("list or retry faxes that failed too many times")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("list")
+                    .about("list quarantined faxes")
+                    .arg(Arg::new("account").long("account")),
+            )
+            .subcommand(
+                Command::new("retry")
+                    .about("release a fax so the next poll tries it again")
+                    .arg(Arg::new("account").long("account").required(true))
+                    .arg(
+                        Arg::new("file")
+                            .long("file")
+                            .required(true)
+                            .help("srfax filename, including the |details id"),
+                    ),
+            ),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("quarantine") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+    let ledger = Ledger::open()?;
+
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let account = matches.get_one::<String>("account").map(|s| s.as_str());
+
+        for record in ledger.quarantined(account)? {
+            println!(
+                "{}\t{}\tfailures={}\tquarantined={}\terror={}",
+                record.account,
+                record.filename,
+                record.failures,
+                record.quarantined_at.unwrap_or_default(),
+                record.last_error.unwrap_or_default(),
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("retry") {
+        let account: &String = unwrap!(matches.get_one("account"));
+        let file: &String = unwrap!(matches.get_one("file"));
+
+        if !ledger.release(account, file)? {
+            return Err(QuarantineError::NotQuarantined(file.clone()));
+        }
+        println!("released {}, it will be retried on the next poll", file);
+    }
+
+    Ok(true)
+}
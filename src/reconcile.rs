@@ -0,0 +1,342 @@
+use crate::{
+    config::{self, ConfigError, Srfax},
+    document, encryption,
+    ledger::{FaxRecord, Ledger, LedgerError},
+    metadata,
+    response::{Direction, DownloadFormat, InboxItem, ResultStatus},
+    srfax::{self, SrfaxError},
+    srfax_service,
+    storage::{self, Storage, StorageError},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use reqwest::blocking::Client;
+use std::collections::HashSet;
+
+#[derive(Debug, Error)]
+pub enum ReconcileError {
+    #[error("Reconcile(Config({0:?}))")]
+    Config(#[from] ConfigError),
+
+    #[error("Reconcile(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Reconcile(Srfax({0:?}))")]
+    Srfax(#[from] SrfaxError),
+
+    #[error("Reconcile(SrfaxService({0:?}))")]
+    SrfaxService(#[from] srfax_service::Error),
+
+    #[error("Reconcile(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Reconcile(failed to get inbox for {0})")]
+    FailedToGetInbox(String),
+
+    #[error("Reconcile(no srfax named {0:?})")]
+    UnknownAccount(String),
+}
+type Result<T> = std::result::Result<T, ReconcileError>;
+
+/// differences between the srfax inbox and the storage of one account,
+/// files are named by their location
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    pub remote: usize,
+    pub local: usize,
+    /// in the inbox but not stored, with where it should be
+    pub missing: Vec<(InboxItem, String)>,
+    /// stored but not in the inbox, and not deleted from srfax or archived by
+    /// us
+    pub orphaned: Vec<String>,
+    /// (location, srfax pages, local pages)
+    pub page_mismatches: Vec<(String, usize, usize)>,
+    /// (location, srfax size, local size)
+    pub size_mismatches: Vec<(String, u64, u64)>,
+}
+
+pub fn reconcile(
+    client: &Client,
+    srfax: &Srfax,
+    ledger: &Ledger,
+    storage: &dyn Storage,
+) -> Result<Reconciliation> {
+    let inbox = srfax::get_fax_inbox(client, srfax)?;
+    if inbox.Status != ResultStatus::Success {
+        return Err(ReconcileError::FailedToGetInbox(srfax.name.clone()));
+    }
+    let inbox = inbox.Result.unwrap_or_default();
+
+    let mut local = storage.list()?;
+    local.retain(|key| !metadata::is_sidecar(srfax, key));
+    let mut result = Reconciliation {
+        remote: inbox.len(),
+        local: local.len(),
+        ..Default::default()
+    };
+
+    let formats = srfax.formats();
+    let in_srfax: HashSet<String> = inbox.iter().map(|item| item.FileName.clone()).collect();
+    let mut expected: HashSet<String> = HashSet::new();
+    for item in inbox {
+        let record = ledger.get(&srfax.name, &item.FileName)?;
+        let base = match record
+            .as_ref()
+            .and_then(|record| record.local_path.as_deref())
+            .and_then(|location| storage.key(location))
+        {
+            Some(key) => key,
+            None => {
+                let barcodes = record.map(|record| record.barcodes).unwrap_or_default();
+                srfax::fax_key(srfax, &item, &formats[0], &barcodes)?
+            }
+        };
+
+        let copies = ledger.stored_copies(&srfax.name, &item.FileName)?;
+        // ocr output and anything else the ledger keeps next to the fax
+        for copy in copies.iter().filter(|copy| copy.is_present()) {
+            if let Some(key) = copy.location.as_deref().and_then(|l| storage.key(l)) {
+                expected.insert(key);
+            }
+        }
+        for (i, format) in formats.iter().enumerate() {
+            let key = srfax::format_key(&base, format);
+            expected.insert(key.clone());
+
+            let location = storage.location(&key);
+            // archived or deleted by local retention on purpose
+            if copies.iter().any(|copy| {
+                copy.location.as_deref() == Some(location.as_str())
+                    && (copy.archive.is_some() || copy.purged_at.is_some())
+            }) {
+                continue;
+            }
+            if !storage.exists(&key)? {
+                result.missing.push((item.clone(), location));
+                continue;
+            }
+
+            // without the private key there's nothing to compare
+            if encryption::is_encrypted(&key) {
+                continue;
+            }
+
+            let data = storage.get(&key)?;
+            let pages = item.Pages.trim().parse::<usize>().ok();
+            let local_pages = document::page_count(&data, format);
+            if let (Some(pages), Some(local_pages)) = (pages, local_pages) {
+                if pages != local_pages {
+                    result
+                        .page_mismatches
+                        .push((location.clone(), pages, local_pages));
+                }
+            }
+            // srfax reports a single size, checked against the first format
+            if i > 0 {
+                continue;
+            }
+            if let Ok(size) = item.Size.trim().parse::<u64>() {
+                if size != data.len() as u64 {
+                    result
+                        .size_mismatches
+                        .push((location.clone(), size, data.len() as u64));
+                }
+            }
+        }
+    }
+
+    for key in local {
+        if expected.contains(&key) {
+            continue;
+        }
+        let location = storage.location(&key);
+        if ledger.is_version(&srfax.name, &location)?
+            || ledger.is_archive(&srfax.name, &location)?
+        {
+            continue;
+        }
+        // any copy of a fax srfax still has, or that we deleted from srfax
+        let known = find_fax(srfax, ledger, storage, &key)?.is_some_and(|record| {
+            record.deleted_at.is_some() || in_srfax.contains(&record.filename)
+        });
+        if !known {
+            result.orphaned.push(location);
+        }
+    }
+
+    Ok(result)
+}
+
+/// the fax a stored file belongs to. ocr output the ledger has no copy of,
+/// like after a failed destination, belongs to the fax it was made from
+fn find_fax(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    storage: &dyn Storage,
+    key: &str,
+) -> Result<Option<FaxRecord>> {
+    if let Some(record) = ledger.find_by_location(&srfax.name, &storage.location(key))? {
+        return Ok(Some(record));
+    }
+    let base = match key
+        .strip_suffix(".ocr.pdf")
+        .or_else(|| key.strip_suffix(".ocr.txt"))
+    {
+        Some(base) => base,
+        None => return Ok(None),
+    };
+    // ocr reads the tiff if there is one, otherwise the pdf
+    for format in [DownloadFormat::TIF, DownloadFormat::PDF] {
+        let location = storage.location(&format!("{}.{}", base, format));
+        if let Some(record) = ledger.find_by_location(&srfax.name, &location)? {
+            return Ok(Some(record));
+        }
+    }
+    Ok(None)
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("reconcile")
+            .about("compare the srfax inbox with the downloaded files")
+            .arg(Arg::new("account").long("account"))
+            .arg(
+                Arg::new("download")
+                    .long("download")
+                    .action(ArgAction::SetTrue)
+                    .help("download faxes missing locally"),
+            ),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("reconcile") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+    let account = matches.get_one::<String>("account");
+
+    let srfaxes: Vec<Srfax> = config::get_srfaxes()?
+        .into_iter()
+        .filter(|srfax| account.is_none_or(|name| *name == srfax.name))
+        .collect();
+    if let (Some(name), true) = (account, srfaxes.is_empty()) {
+        return Err(ReconcileError::UnknownAccount(name.clone()));
+    }
+
+    // downloads write `.part` files like polling does
+    let _lock = match matches.get_flag("download") {
+        true => Some(srfax_service::lock_downloads()?),
+        false => None,
+    };
+    let client = Client::new();
+    let ledger = Ledger::open()?;
+
+    for srfax in &srfaxes {
+        // mirrors are checked against the primary destination
+        let destinations = storage::open_destinations(srfax)?;
+        let result = reconcile(&client, srfax, &ledger, destinations[0].storage()?)?;
+
+        println!(
+            "{}: {} in srfax, {} local files",
+            srfax.name, result.remote, result.local
+        );
+        for (item, location) in &result.missing {
+            println!("  missing locally: {} ({})", item.FileName, location);
+        }
+        for location in &result.orphaned {
+            println!("  not in srfax: {}", location);
+        }
+        for (location, pages, local) in &result.page_mismatches {
+            println!(
+                "  page mismatch: {} srfax={} local={}",
+                location, pages, local
+            );
+        }
+        for (location, size, local) in &result.size_mismatches {
+            println!(
+                "  size mismatch: {} srfax={} local={}",
+                location, size, local
+            );
+        }
+
+        if matches.get_flag("download") {
+            let mut done = HashSet::new();
+            for (item, _) in &result.missing {
+                // every missing format is fetched with the first one
+                if !done.insert(&item.FileName) {
+                    continue;
+                }
+                match srfax::redownload_fax(
+                    &client,
+                    srfax,
+                    &ledger,
+                    &destinations,
+                    item,
+                    Direction::IN,
+                ) {
+                    Ok(location) => println!("  downloaded {} to {}", item.FileName, location),
+                    Err(e) => println!("  failed to download {}: {}", item.FileName, e),
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{LocalStorage, Permissions};
+
+    #[test]
+    fn finds_the_fax_of_ocr_output() {
+        let srfax: Srfax = serde_json::from_str(
+            r#"{"name": "clinic", "access_id": "1", "access_pwd": "pwd",
+                "file_dir": "/srv/faxes", "download_fmt": "TIF", "delete_after": false}"#,
+        )
+        .unwrap();
+        let storage = LocalStorage::new("/srv/faxes", Permissions::default());
+        let ledger = Ledger::in_memory();
+        let filename = "20261019120000-1234-5_1|98765";
+        ledger
+            .record_seen(
+                "clinic",
+                &InboxItem {
+                    FileName: filename.to_string(),
+                    ReceiveStatus: "Ok".to_string(),
+                    Date: "Oct 19/2026 09:15 AM".to_string(),
+                    CallerID: "4165551234".to_string(),
+                    RemoteID: "Dr Smith".to_string(),
+                    Pages: "2".to_string(),
+                    Size: "426".to_string(),
+                },
+            )
+            .unwrap();
+        ledger
+            .record_download("clinic", filename, "abc", &storage.location("a.TIF"))
+            .unwrap();
+        ledger
+            .record_stored(
+                "clinic",
+                filename,
+                "default",
+                crate::ocr::OCR_PDF,
+                &storage.location("a.ocr.pdf"),
+                "def",
+            )
+            .unwrap();
+
+        // recorded, and left behind by a failed ocr run
+        for key in ["a.TIF", "a.ocr.pdf", "a.ocr.txt"] {
+            let record = find_fax(&srfax, &ledger, &storage, key).unwrap();
+            assert_eq!(
+                record.map(|record| record.filename).as_deref(),
+                Some(filename)
+            );
+        }
+        for key in ["b.TIF", "b.ocr.pdf", "a.txt"] {
+            assert!(find_fax(&srfax, &ledger, &storage, key).unwrap().is_none());
+        }
+    }
+}
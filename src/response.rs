@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use core::fmt;
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
@@ -6,6 +7,7 @@ pub enum ResultStatus {
     Failed,
 }
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum DownloadFormat {
     PDF,
     TIF,
@@ -17,6 +19,7 @@ impl fmt::Display for DownloadFormat {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Direction {
     IN,
     OUT,
@@ -39,6 +42,22 @@ pub struct InboxItem {
     pub Size: String,
 }
 
+impl InboxItem {
+    /// when srfax received the fax, srfax has used a few formats for `Date`
+    pub fn received(&self) -> Option<NaiveDateTime> {
+        const FORMATS: &[&str] = &[
+            "%b %d/%Y %I:%M %p",
+            "%b %d/%Y %H:%M",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%d %H:%M",
+        ];
+
+        FORMATS
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(self.Date.trim(), fmt).ok())
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Inbox {
@@ -59,3 +78,10 @@ pub struct DeleteFaxResponse {
     pub Status: ResultStatus,
     pub Result: String, // error message
 }
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(non_snake_case)]
+pub struct UsageResponse {
+    pub Status: ResultStatus,
+    pub Result: serde_json::Value, // usage on success, error message otherwise
+}
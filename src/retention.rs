@@ -0,0 +1,857 @@
+use crate::{
+    archive::{self, ArchiveError},
+    config::{self, ConfigError, Srfax},
+    encryption::{self, EncryptionError},
+    ledger::{self, FaxRecord, Ledger, LedgerError, StoredCopy},
+    metadata,
+    response::{InboxItem, ResultStatus},
+    srfax::{self, SrfaxError},
+    srfax_service::{self, Report},
+    storage::{self, Storage, StorageError},
+};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use reqwest::blocking::Client;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Error)]
+pub enum RetentionError {
+    #[error("Retention(Config({0:?}))")]
+    Config(#[from] ConfigError),
+
+    #[error("Retention(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Retention(Srfax({0:?}))")]
+    Srfax(#[from] SrfaxError),
+
+    #[error("Retention(SrfaxService({0:?}))")]
+    SrfaxService(#[from] srfax_service::Error),
+
+    #[error("Retention(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Retention(Archive({0:?}))")]
+    Archive(#[from] ArchiveError),
+
+    #[error("Retention(Encryption({0:?}))")]
+    Encryption(#[from] EncryptionError),
+
+    #[error("Retention(Json({0:?}))")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Retention(failed to get inbox for {0})")]
+    FailedToGetInbox(String),
+
+    #[error("Retention(no srfax named {0:?})")]
+    UnknownAccount(String),
+}
+type Result<T> = std::result::Result<T, RetentionError>;
+
+/// faxes in the inbox that may be deleted from srfax now
+pub fn due_for_remote_delete<'a>(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    inbox: &'a [InboxItem],
+) -> std::result::Result<Vec<&'a InboxItem>, LedgerError> {
+    let now = Local::now();
+    let required = storage::required_destinations(srfax);
+    let formats: Vec<String> = srfax.formats().iter().map(|f| f.to_string()).collect();
+    let mut due = vec![];
+
+    for item in inbox {
+        if let Some(record) = ledger.get(&srfax.name, &item.FileName)? {
+            if is_due(srfax, &record, now)
+                && stored_everywhere(ledger, &srfax.name, &item.FileName, &required, &formats)?
+            {
+                due.push(item);
+            }
+        }
+    }
+
+    Ok(due)
+}
+
+/// whether every required destination has a copy of the fax in every format,
+/// archives count but copies deleted by local retention don't
+fn stored_everywhere(
+    ledger: &Ledger,
+    account: &str,
+    filename: &str,
+    required: &[String],
+    formats: &[String],
+) -> std::result::Result<bool, LedgerError> {
+    let copies = ledger.stored_copies(account, filename)?;
+
+    Ok(required.iter().all(|name| {
+        formats.iter().all(|format| {
+            copies.iter().any(|copy| {
+                copy.destination == *name
+                    && copy.format == *format
+                    && copy.stored_at.is_some()
+                    && copy.purged_at.is_none()
+            })
+        })
+    }))
+}
+
+/// only verified downloads are ever deleted, `delete_after` deletes them
+/// `delete_delay_days` after download, `remote_retention_days` once they
+/// were received that many days ago
+fn is_due(srfax: &Srfax, record: &FaxRecord, now: DateTime<Local>) -> bool {
+    let verified_at = match record
+        .verified_at
+        .as_deref()
+        .map(DateTime::parse_from_rfc3339)
+    {
+        Some(Ok(t)) => t,
+        _ => return false,
+    };
+
+    if srfax.delete_after
+        && now.fixed_offset() - verified_at >= Duration::days(srfax.delete_delay_days as i64)
+    {
+        return true;
+    }
+
+    match (srfax.remote_retention_days, received_at(record)) {
+        (Some(days), Some(received)) => now - received >= Duration::days(days as i64),
+        _ => false,
+    }
+}
+
+/// when srfax received the fax, or when we first saw it if srfax's date
+/// couldn't be parsed
+fn received_at(record: &FaxRecord) -> Option<DateTime<Local>> {
+    if let Some(ref received) = record.received_at {
+        let naive = NaiveDateTime::parse_from_str(received, "%Y-%m-%d %H:%M:%S").ok()?;
+        return Local.from_local_datetime(&naive).earliest();
+    }
+
+    DateTime::parse_from_rfc3339(&record.first_seen)
+        .ok()
+        .map(|t| t.with_timezone(&Local))
+}
+
+/// name of the manifest in every archive, `.age` is added when encrypting
+const ARCHIVE_MANIFEST: &str = "manifest.json";
+
+/// what local retention is due to do at one destination
+#[derive(Debug, Default)]
+pub struct LocalPlan {
+    /// archives whose faxes are all due for deletion, as (archive key, its
+    /// faxes)
+    pub delete_archives: Vec<(String, Vec<(FaxRecord, StoredCopy)>)>,
+    /// faxes due for deletion that were never archived
+    pub delete: Vec<(FaxRecord, StoredCopy)>,
+    /// faxes due for archiving, by the key of their monthly archive
+    pub archive: BTreeMap<String, Vec<(FaxRecord, StoredCopy)>>,
+}
+
+/// faxes at a destination that are due for archiving or deletion, by when
+/// they were received. archives are only deleted once every fax in them is
+/// due
+pub fn plan_local(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destination: &str,
+    storage: &dyn Storage,
+) -> Result<LocalPlan> {
+    let now = Local::now();
+    let older_than = |record: &FaxRecord, days: u32| {
+        received_at(record).is_some_and(|t| now - t >= Duration::days(days as i64))
+    };
+    let mut plan = LocalPlan::default();
+
+    if let Some(days) = srfax.local_retention_days {
+        for (location, _sha256) in ledger.archives(&srfax.name, destination)? {
+            let key = match storage.key(&location) {
+                Some(key) => key,
+                None => {
+                    warn!("archive {} is not in {} anymore", location, destination);
+                    continue;
+                }
+            };
+            let faxes = with_records(
+                srfax,
+                ledger,
+                ledger.archived_in(&srfax.name, destination, &location)?,
+            )?;
+            if faxes.iter().all(|(record, _)| older_than(record, days)) {
+                plan.delete_archives.push((key, faxes));
+            }
+        }
+    }
+
+    let copies = ledger.copies_at(&srfax.name, destination)?;
+    for (record, copy) in with_records(srfax, ledger, copies)? {
+        if srfax
+            .local_retention_days
+            .is_some_and(|days| older_than(&record, days))
+        {
+            plan.delete.push((record, copy));
+        } else if srfax
+            .archive_after_days
+            .is_some_and(|days| older_than(&record, days))
+        {
+            let month = match received_at(&record) {
+                Some(received) => received.format("%Y-%m").to_string(),
+                None => continue,
+            };
+            let key = archive_key(srfax, &month);
+            plan.archive.entry(key).or_default().push((record, copy));
+        }
+    }
+
+    Ok(plan)
+}
+
+fn with_records(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    copies: Vec<(String, StoredCopy)>,
+) -> Result<Vec<(FaxRecord, StoredCopy)>> {
+    let mut found = vec![];
+    for (filename, copy) in copies {
+        if let Some(record) = ledger.get(&srfax.name, &filename)? {
+            found.push((record, copy));
+        }
+    }
+    Ok(found)
+}
+
+/// `<archive_dir>/<yyyy>-<mm>.zip`
+fn archive_key(srfax: &Srfax, month: &str) -> String {
+    let name = format!("{}.{}", month, srfax.archive_format.extension());
+    match srfax.archive_dir.trim_matches('/') {
+        "" => name,
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+/// what `apply_local` did
+#[derive(Debug, Default)]
+pub struct LocalReport {
+    pub archived: usize,
+    pub deleted: usize,
+}
+
+/// deletes and archives what `plan_local` says is due, every action is logged
+pub fn apply_local(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destination: &str,
+    storage: &dyn Storage,
+) -> Result<LocalReport> {
+    let plan = plan_local(srfax, ledger, destination, storage)?;
+    let mut report = LocalReport::default();
+
+    for (key, faxes) in &plan.delete_archives {
+        let location = storage.location(key);
+        if storage.exists(key)? {
+            storage.remove(key)?;
+        }
+        for (record, copy) in faxes {
+            ledger.record_purged(&srfax.name, &record.filename, destination, &copy.format)?;
+        }
+        ledger.forget_archive(&srfax.name, destination, &location)?;
+        info!(
+            "local retention: deleted archive {} holding {} files",
+            location,
+            faxes.len()
+        );
+        report.deleted += faxes.len();
+    }
+
+    for (record, copy) in &plan.delete {
+        if let Some(key) = copy.location.as_deref().and_then(|l| storage.key(l)) {
+            remove_with_sidecar(srfax, storage, &key)?;
+        }
+        ledger.record_purged(&srfax.name, &record.filename, destination, &copy.format)?;
+        info!(
+            "local retention: deleted {} received {}",
+            copy.location.as_deref().unwrap_or_default(),
+            record.received
+        );
+        report.deleted += 1;
+    }
+
+    for (key, faxes) in &plan.archive {
+        report.archived += archive_faxes(srfax, ledger, destination, storage, key, faxes)?;
+    }
+
+    Ok(report)
+}
+
+fn remove_with_sidecar(srfax: &Srfax, storage: &dyn Storage, key: &str) -> Result<()> {
+    let sidecar = metadata::sidecar_key(srfax, key);
+    for key in [key, sidecar.as_str()] {
+        if storage.exists(key)? {
+            storage.remove(key)?;
+        }
+    }
+    Ok(())
+}
+
+/// one fax in the manifest of an archive
+#[derive(Debug, Serialize)]
+struct ArchivedFax<'a> {
+    /// the key the fax had, which is its name in the archive
+    file: String,
+    srfax_filename: &'a str,
+    format: &'a str,
+    caller_id: &'a str,
+    remote_id: &'a str,
+    /// `YYYY-MM-DD HH:MM:SS`, if srfax's date could be read
+    received: Option<&'a str>,
+    received_raw: &'a str,
+    pages: Option<i64>,
+    sha256: Option<&'a str>,
+}
+
+/// adds faxes to the archive at `key`, creating it if needed, and removes
+/// them and their sidecars once the archive was read back. returns how many
+/// were archived
+fn archive_faxes(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destination: &str,
+    storage: &dyn Storage,
+    key: &str,
+    faxes: &[(FaxRecord, StoredCopy)],
+) -> Result<usize> {
+    let location = storage.location(key);
+    let mut entries: Vec<(String, Vec<u8>)> = vec![];
+    let mut added = vec![];
+
+    for (record, copy) in faxes {
+        let fax_key = match copy.location.as_deref().and_then(|l| storage.key(l)) {
+            Some(fax_key) => fax_key,
+            None => continue,
+        };
+        if !storage.exists(&fax_key)? {
+            warn!(
+                "{} is missing, not archiving it. see `verify`",
+                storage.location(&fax_key)
+            );
+            continue;
+        }
+        let data = storage.get(&fax_key)?;
+        if copy.sha256.as_deref() != Some(ledger::sha256_hex(&data).as_str()) {
+            warn!(
+                "{} changed since download, not archiving it",
+                storage.location(&fax_key)
+            );
+            continue;
+        }
+        entries.push((fax_key.clone(), data));
+
+        let sidecar = metadata::sidecar_key(srfax, &fax_key);
+        if srfax.sidecar && storage.exists(&sidecar)? {
+            entries.push((sidecar.clone(), storage.get(&sidecar)?));
+        }
+        added.push((record, copy, fax_key));
+    }
+    if added.is_empty() {
+        return Ok(0);
+    }
+
+    // faxes that became due since the archive was written are added to it
+    let manifest = format!("{}{}", ARCHIVE_MANIFEST, encryption::suffix(srfax));
+    let known = ledger
+        .archives(&srfax.name, destination)?
+        .into_iter()
+        .find(|(l, _)| *l == location)
+        .map(|(_, sha256)| sha256);
+    if storage.exists(key)? {
+        let data = storage.get(key)?;
+        // an archive the ledger doesn't know is one that was written just
+        // before a crash
+        if known.is_some_and(|sha256| sha256 != ledger::sha256_hex(&data)) {
+            warn!(
+                "{} changed since it was written, not adding to it",
+                location
+            );
+            return Ok(0);
+        }
+        for (name, data) in archive::read(srfax.archive_format, &data)? {
+            if name != manifest && !entries.iter().any(|(n, _)| *n == name) {
+                entries.push((name, data));
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut archived = with_records(
+        srfax,
+        ledger,
+        ledger.archived_in(&srfax.name, destination, &location)?,
+    )?;
+    archived.extend(faxes.iter().cloned());
+    let listed: Vec<ArchivedFax> = archived
+        .iter()
+        .filter_map(|(record, copy)| {
+            let file = copy.location.as_deref().and_then(|l| storage.key(l))?;
+            entries
+                .iter()
+                .any(|(name, _)| *name == file)
+                .then(|| ArchivedFax {
+                    file,
+                    srfax_filename: &record.filename,
+                    format: &copy.format,
+                    caller_id: &record.caller_id,
+                    remote_id: &record.remote_id,
+                    received: record.received_at.as_deref(),
+                    received_raw: &record.received,
+                    pages: record.pages,
+                    sha256: copy.sha256.as_deref(),
+                })
+        })
+        .collect();
+    let listed = encryption::seal(srfax, serde_json::to_string_pretty(&listed)?.as_bytes())?;
+    entries.push((manifest, listed));
+
+    let data = archive::write(srfax.archive_format, &entries)?;
+    let sha256 = ledger::sha256_hex(&data);
+    storage.put(key, &data)?;
+    // the faxes are only removed once the archive is known to be whole
+    if ledger::sha256_hex(&storage.get(key)?) != sha256 {
+        warn!(
+            "{} did not read back as written, keeping the faxes",
+            location
+        );
+        return Ok(0);
+    }
+    ledger.record_archive(&srfax.name, destination, &location, &sha256)?;
+
+    for (record, copy, fax_key) in &added {
+        ledger.record_archived(
+            &srfax.name,
+            &record.filename,
+            destination,
+            &copy.format,
+            &location,
+        )?;
+        remove_with_sidecar(srfax, storage, fax_key)?;
+        info!(
+            "local retention: archived {} into {}",
+            storage.location(fax_key),
+            location
+        );
+    }
+
+    Ok(added.len())
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("retention")
+            .about("apply retention policies now")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("remote")
+                    .about("delete faxes from srfax that are due for deletion")
+                    .arg(Arg::new("account").long("account"))
+                    .arg(
+                        Arg::new("dry-run")
+                            .long("dry-run")
+                            .action(ArgAction::SetTrue)
+                            .help("only list what would be deleted"),
+                    ),
+            )
+            .subcommand(
+                Command::new("local")
+                    .about("archive and delete downloaded faxes that are due")
+                    .arg(Arg::new("account").long("account"))
+                    .arg(
+                        Arg::new("dry-run")
+                            .long("dry-run")
+                            .action(ArgAction::SetTrue)
+                            .help("only list what would be archived or deleted"),
+                    ),
+            ),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> crate::Result<bool> {
+    let matches = match matches.subcommand_matches("retention") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+
+    if let Some(matches) = matches.subcommand_matches("remote") {
+        crate::setup_logging()?;
+        remote(
+            matches.get_one::<String>("account"),
+            matches.get_flag("dry-run"),
+        )?;
+    }
+    if let Some(matches) = matches.subcommand_matches("local") {
+        crate::setup_logging()?;
+        local(
+            matches.get_one::<String>("account"),
+            matches.get_flag("dry-run"),
+        )?;
+    }
+
+    Ok(true)
+}
+
+fn remote(account: Option<&String>, dry_run: bool) -> Result<()> {
+    let client = Client::new();
+    let ledger = Ledger::open()?;
+
+    for srfax in config::get_srfaxes()? {
+        if account.is_some_and(|name| *name != srfax.name) {
+            continue;
+        }
+        if !srfax.delete_after && srfax.remote_retention_days.is_none() {
+            continue;
+        }
+
+        let inbox = srfax::get_fax_inbox(&client, &srfax)?;
+        if inbox.Status != ResultStatus::Success {
+            return Err(RetentionError::FailedToGetInbox(srfax.name.clone()));
+        }
+        let inbox = inbox.Result.unwrap_or_default();
+
+        if dry_run {
+            for item in due_for_remote_delete(&srfax, &ledger, &inbox)? {
+                println!(
+                    "{}: would delete {} received {}",
+                    srfax.name, item.FileName, item.Date
+                );
+            }
+        } else {
+            let mut report = Report::default();
+            srfax_service::delete_verified(&client, &srfax, &ledger, &inbox, &mut report)?;
+            println!(
+                "{}: deleted={} delete_errors={}",
+                srfax.name, report.deleted, report.delete_errors
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn local(account: Option<&String>, dry_run: bool) -> Result<()> {
+    let srfaxes: Vec<Srfax> = config::get_srfaxes()?
+        .into_iter()
+        .filter(|srfax| account.is_none_or(|name| *name == srfax.name))
+        .collect();
+    if let (Some(name), true) = (account, srfaxes.is_empty()) {
+        return Err(RetentionError::UnknownAccount(name.clone()));
+    }
+    let ledger = Ledger::open()?;
+
+    for srfax in &srfaxes {
+        if srfax.archive_after_days.is_none() && srfax.local_retention_days.is_none() {
+            continue;
+        }
+
+        for destination in storage::open_destinations(srfax)? {
+            let name = format!("{} ({})", srfax.name, destination.name);
+            let storage = match destination.storage() {
+                Ok(storage) => storage,
+                Err(e) => {
+                    println!("{}: skipped: {}", name, e);
+                    continue;
+                }
+            };
+
+            if !dry_run {
+                let report = apply_local(srfax, &ledger, &destination.name, storage)?;
+                println!(
+                    "{}: archived={} deleted={}",
+                    name, report.archived, report.deleted
+                );
+                continue;
+            }
+
+            let plan = plan_local(srfax, &ledger, &destination.name, storage)?;
+            for (key, faxes) in &plan.delete_archives {
+                println!(
+                    "{}: would delete {} holding {} files",
+                    name,
+                    storage.location(key),
+                    faxes.len()
+                );
+            }
+            for (record, copy) in &plan.delete {
+                println!(
+                    "{}: would delete {} received {}",
+                    name,
+                    copy.location.as_deref().unwrap_or_default(),
+                    record.received
+                );
+            }
+            for (key, faxes) in &plan.archive {
+                for (record, copy) in faxes {
+                    println!(
+                        "{}: would archive {} received {} into {}",
+                        name,
+                        copy.location.as_deref().unwrap_or_default(),
+                        record.received,
+                        storage.location(key)
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::ArchiveFormat,
+        common,
+        storage::{LocalStorage, Permissions, DEFAULT_DESTINATION},
+    };
+
+    fn srfax_with(settings: serde_json::Value) -> Srfax {
+        let mut srfax = serde_json::json!({
+            "name": "clinic",
+            "access_id": "1",
+            "access_pwd": "pwd",
+            "file_dir": "/srv/faxes",
+            "download_fmt": "PDF",
+            "delete_after": true,
+        });
+        srfax
+            .as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().unwrap().clone());
+        serde_json::from_value(srfax).unwrap()
+    }
+
+    fn item(filename: &str) -> InboxItem {
+        received(filename, "Oct 19/2026 09:15 AM")
+    }
+
+    fn received(filename: &str, date: &str) -> InboxItem {
+        InboxItem {
+            FileName: filename.to_string(),
+            ReceiveStatus: "Ok".to_string(),
+            Date: date.to_string(),
+            CallerID: "4165551234".to_string(),
+            RemoteID: "Dr Smith".to_string(),
+            Pages: "2".to_string(),
+            Size: "426".to_string(),
+        }
+    }
+
+    /// a fax downloaded and verified at `verified_at`
+    fn record(verified_at: Option<&str>) -> FaxRecord {
+        let ledger = Ledger::in_memory();
+        ledger.record_seen("clinic", &item("a|1")).unwrap();
+        let mut record = ledger.get("clinic", "a|1").unwrap().unwrap();
+        record.verified_at = verified_at.map(str::to_string);
+        record
+    }
+
+    fn at(time: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn waits_for_delete_delay() {
+        let srfax = srfax_with(serde_json::json!({"delete_delay_days": 3}));
+        let record = record(Some("2026-10-19T09:20:00-04:00"));
+
+        assert!(!is_due(&srfax, &record, at("2026-10-22T09:19:59-04:00")));
+        assert!(is_due(&srfax, &record, at("2026-10-22T09:20:00-04:00")));
+
+        // delete_after off, nothing is ever due by delay
+        let kept = srfax_with(serde_json::json!({"delete_after": false}));
+        assert!(!is_due(&kept, &record, at("2027-10-19T09:20:00-04:00")));
+    }
+
+    #[test]
+    fn never_due_unverified() {
+        let srfax = srfax_with(serde_json::json!({
+            "delete_after": false,
+            "remote_retention_days": 1,
+        }));
+        let later = at("2027-10-19T09:20:00-04:00");
+
+        assert!(!is_due(&srfax, &record(None), later));
+        assert!(!is_due(&srfax, &record(Some("not a date")), later));
+        assert!(is_due(
+            &srfax,
+            &record(Some("2026-10-19T09:20:00-04:00")),
+            later
+        ));
+    }
+
+    #[test]
+    fn remote_retention_boundary() {
+        let srfax = srfax_with(serde_json::json!({
+            "delete_after": false,
+            "remote_retention_days": 30,
+        }));
+        let record = record(Some("2026-10-19T09:20:00-04:00"));
+        assert_eq!(record.received_at.as_deref(), Some("2026-10-19 09:15:00"));
+        let due = received_at(&record).unwrap() + Duration::days(30);
+
+        assert!(!is_due(&srfax, &record, due - Duration::seconds(1)));
+        assert!(is_due(&srfax, &record, due));
+        assert!(is_due(&srfax, &record, due + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn remote_retention_without_received_date() {
+        let srfax = srfax_with(serde_json::json!({
+            "delete_after": false,
+            "remote_retention_days": 30,
+        }));
+        let mut record = record(Some("2026-10-19T09:20:00-04:00"));
+
+        // falls back to when the fax was first seen
+        record.received_at = None;
+        record.first_seen = "2026-10-19T09:20:00-04:00".to_string();
+        assert!(!is_due(&srfax, &record, at("2026-11-18T09:19:59-04:00")));
+        assert!(is_due(&srfax, &record, at("2026-11-18T09:20:00-04:00")));
+
+        // a date that can't be read is never due
+        record.first_seen = "yesterday".to_string();
+        assert!(!is_due(&srfax, &record, at("2027-10-19T09:20:00-04:00")));
+        record.received_at = Some("Oct 19/2026".to_string());
+        assert!(!is_due(&srfax, &record, at("2027-10-19T09:20:00-04:00")));
+    }
+
+    #[test]
+    fn due_once_stored_at_every_required_destination() {
+        let srfax = srfax_with(serde_json::json!({
+            "destinations": [
+                {"name": "nas", "type": "local", "path": "/srv/nas"},
+                {"name": "mirror", "type": "local", "path": "/srv/mirror"},
+                {"name": "offsite", "type": "local", "path": "/srv/offsite", "required": false},
+            ],
+        }));
+        let ledger = Ledger::in_memory();
+        let inbox = [item("a|1")];
+        ledger.record_seen("clinic", &inbox[0]).unwrap();
+        ledger
+            .record_download("clinic", "a|1", "abc", "/srv/nas/a.PDF")
+            .unwrap();
+        let due = || {
+            due_for_remote_delete(&srfax, &ledger, &inbox)
+                .unwrap()
+                .len()
+        };
+        let store = |destination: &str| {
+            ledger
+                .record_stored("clinic", "a|1", destination, "PDF", "a.PDF", "abc")
+                .unwrap()
+        };
+
+        store("nas");
+        assert_eq!(due(), 0);
+        // the optional destination isn't waited for
+        store("mirror");
+        assert_eq!(due(), 1);
+
+        // archived still counts, deleted by local retention doesn't
+        ledger
+            .record_archived("clinic", "a|1", "mirror", "PDF", "2026-10.zip")
+            .unwrap();
+        assert_eq!(due(), 1);
+        ledger
+            .record_purged("clinic", "a|1", "mirror", "PDF")
+            .unwrap();
+        assert_eq!(due(), 0);
+    }
+
+    /// two faxes from september 2025 stored in a new directory
+    fn stored_faxes(format: &str) -> (Srfax, Ledger, LocalStorage, std::path::PathBuf) {
+        let dir = common::private_temp_dir("srfax-test").unwrap();
+        let srfax = srfax_with(serde_json::json!({
+            "file_dir": dir,
+            "archive_after_days": 30,
+            "archive_format": format,
+        }));
+        let storage = LocalStorage::new(dir.to_str().unwrap(), Permissions::default());
+        let ledger = Ledger::in_memory();
+        for (filename, key) in [("a|1", "a.PDF"), ("b|2", "b.PDF")] {
+            let data = format!("fax {}", filename);
+            ledger
+                .record_seen("clinic", &received(filename, "Sep 02/2025 09:15 AM"))
+                .unwrap();
+            storage.put(key, data.as_bytes()).unwrap();
+            ledger
+                .record_stored(
+                    "clinic",
+                    filename,
+                    DEFAULT_DESTINATION,
+                    "PDF",
+                    &storage.location(key),
+                    &ledger::sha256_hex(data.as_bytes()),
+                )
+                .unwrap();
+        }
+        (srfax, ledger, storage, dir)
+    }
+
+    #[test]
+    fn archives_old_faxes() {
+        for (format, archive_format) in [
+            ("zip", ArchiveFormat::Zip),
+            ("tar.zst", ArchiveFormat::TarZst),
+        ] {
+            let (srfax, ledger, storage, dir) = stored_faxes(format);
+            let key = format!("archive/2025-09.{}", format);
+
+            let report = apply_local(&srfax, &ledger, DEFAULT_DESTINATION, &storage).unwrap();
+            assert_eq!(report.archived, 2);
+            assert_eq!(report.deleted, 0);
+            assert_eq!(storage.list().unwrap(), [key.as_str()]);
+
+            let entries = archive::read(archive_format, &storage.get(&key).unwrap()).unwrap();
+            let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, ["a.PDF", "b.PDF", ARCHIVE_MANIFEST]);
+            assert_eq!(entries[0].1, b"fax a|1");
+
+            let location = storage.location(&key);
+            assert!(ledger.is_archive("clinic", &location).unwrap());
+            for filename in ["a|1", "b|2"] {
+                let copies = ledger.stored_copies("clinic", filename).unwrap();
+                assert_eq!(copies[0].archive.as_deref(), Some(location.as_str()));
+                assert!(copies[0].purged_at.is_none());
+            }
+
+            // nothing left to do
+            let report = apply_local(&srfax, &ledger, DEFAULT_DESTINATION, &storage).unwrap();
+            assert_eq!(report.archived, 0);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn keeps_faxes_until_archive_is_stored() {
+        let (srfax, ledger, storage, dir) = stored_faxes("zip");
+        // the archive directory can't be created
+        std::fs::write(dir.join("archive"), b"").unwrap();
+
+        assert!(apply_local(&srfax, &ledger, DEFAULT_DESTINATION, &storage).is_err());
+        assert!(storage.exists("a.PDF").unwrap());
+        assert!(storage.exists("b.PDF").unwrap());
+        for filename in ["a|1", "b|2"] {
+            let copies = ledger.stored_copies("clinic", filename).unwrap();
+            assert!(copies[0].archive.is_none());
+        }
+
+        std::fs::remove_file(dir.join("archive")).unwrap();
+        let report = apply_local(&srfax, &ledger, DEFAULT_DESTINATION, &storage).unwrap();
+        assert_eq!(report.archived, 2);
+        assert!(!storage.exists("a.PDF").unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,17 @@
-use crate::{config::Srfax, response::*};
+use crate::{
+    barcode::{self, Barcode},
+    config::{Srfax, CONFIG},
+    convert, document,
+    encryption::{self, ENCRYPTED_SUFFIX},
+    ledger::{self, FaxRecord, Ledger},
+    metadata::{self, Metadata},
+    response::*,
+    state::{self, Health},
+    storage::{self, Destination, Storage, StorageError},
+    template,
+};
+use chrono::NaiveDate;
 use reqwest::blocking::{Client, Response};
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::{Path, PathBuf};
 
 #[derive(Debug, Error)]
 pub enum SrfaxError {
@@ -12,33 +21,127 @@ pub enum SrfaxError {
     #[error("Srfax(IO({0:?}))")]
     Io(#[from] std::io::Error),
 
+    #[error("Srfax(Ledger({0:?}))")]
+    Ledger(#[from] crate::ledger::LedgerError),
+
+    #[error("Srfax(Metadata({0:?}))")]
+    Metadata(#[from] crate::metadata::MetadataError),
+
+    #[error("Srfax(Storage({0:?}))")]
+    Storage(#[from] crate::storage::StorageError),
+
+    #[error("Srfax(Template({0:?}))")]
+    Template(#[from] crate::template::TemplateError),
+
+    #[error("Srfax(Encryption({0:?}))")]
+    Encryption(#[from] crate::encryption::EncryptionError),
+
     #[error("Srfax(Base64({0:?}))")]
     Base64(#[from] base64::DecodeError),
 
     #[error("Srafx(possible directory traversal attack! filename={0})")]
     DirectoryTraversal(String),
 
+    #[error("Srfax(verification failed for {0}: {1})")]
+    VerifyFailed(String, String),
+
     #[error("Srfax(failed to download item {0:?})")]
     FailedToDownload(Box<InboxItem>),
 }
 
 type Result<T> = std::result::Result<T, SrfaxError>;
 
-pub const SRFAX_ROOT: &str = "https://www.srfax.com";
+impl SrfaxError {
+    /// whether srfax couldn't be reached, as opposed to a problem with a fax
+    pub fn is_network(&self) -> bool {
+        match self {
+            SrfaxError::Reqwest(e) => e.is_connect() || e.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// whether srfax or the storage couldn't be reached, which says nothing
+    /// about the fax itself
+    pub fn is_outage(&self) -> bool {
+        match self {
+            SrfaxError::Storage(e) => !matches!(e, StorageError::InvalidKey(_)),
+            e => e.is_network(),
+        }
+    }
+}
+
 pub const SRFAX_API: &str = "https://www.srfax.com/SRF_SecWebSvc.php";
 
 pub const SRFAX_ACTION_GET_INBOX: &str = "Get_Fax_Inbox";
 pub const SRFAX_ACTION_RETRIEVE: &str = "Retrieve_Fax";
 pub const SRFAX_ACTION_DELETE: &str = "Delete_Fax";
+pub const SRFAX_ACTION_USAGE: &str = "Get_Fax_Usage";
 
-pub fn test_connection(client: &Client) -> bool {
-    match client.get(SRFAX_ROOT).send() {
-        Ok(resp) => resp.status().is_success(),
-        Err(e) => {
-            warn!("could not connect to srfax! {}", e);
-            false
+/// returns the cached health of the account, probing the api again if the
+/// cached result is stale or was a network error
+pub fn check_health(client: &Client, srfax: &Srfax) -> Health {
+    let cached = state::get(&srfax.name);
+
+    if let Some(checked) = cached.checked {
+        let age = chrono::Local::now() - checked;
+        let fresh = age < chrono::Duration::seconds(CONFIG.health_interval as i64);
+
+        match cached.health {
+            Health::Unknown | Health::NetworkError(_) => (),
+            _ if fresh => return cached.health,
+            _ => (),
         }
     }
+
+    let health = probe(client, srfax);
+    state::set_health(&srfax.name, health.clone());
+
+    health
+}
+
+/// asks srfax for the account usage, which needs valid credentials but has
+/// no side effects
+pub fn probe(client: &Client, srfax: &Srfax) -> Health {
+    let data = vec![("sPeriod", "ALL")];
+
+    let resp = match send_post(client, SRFAX_ACTION_USAGE, data, srfax) {
+        Ok(resp) => resp,
+        Err(e) if e.is_network() => {
+            warn!("could not connect to srfax! {:?}", e);
+            return Health::NetworkError(e.to_string());
+        }
+        Err(e) => return Health::ApiError(e.to_string()),
+    };
+
+    if !resp.status().is_success() {
+        return Health::ApiError(format!("http status {}", resp.status()));
+    }
+
+    let result: UsageResponse = match resp.json() {
+        Ok(result) => result,
+        Err(e) => return Health::ApiError(e.to_string()),
+    };
+
+    if result.Status == ResultStatus::Success {
+        Health::Healthy
+    } else {
+        classify_failure(&result.Result.to_string())
+    }
+}
+
+/// srfax's bad credential messages, lowercase
+const AUTH_FAILURES: &[&str] = &["invalid access code / password", "invalid access id"];
+
+/// srfax reports bad credentials the same way as other failures, so the
+/// message is the only thing to go by
+pub fn classify_failure(msg: &str) -> Health {
+    let lower = msg.trim().to_lowercase();
+
+    if AUTH_FAILURES.contains(&lower.as_str()) {
+        Health::AuthFailed(msg.to_string())
+    } else {
+        Health::ApiError(msg.to_string())
+    }
 }
 
 pub fn get_fax_inbox(client: &Client, srfax: &Srfax) -> Result<Inbox> {
@@ -50,36 +153,361 @@ pub fn get_fax_inbox(client: &Client, srfax: &Srfax) -> Result<Inbox> {
     Ok(obj)
 }
 
+/// lists the faxes received between `start` and `end`, both inclusive
+pub fn get_fax_inbox_range(
+    client: &Client,
+    srfax: &Srfax,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Inbox> {
+    let start = start.format("%Y%m%d").to_string();
+    let end = end.format("%Y%m%d").to_string();
+    let data = vec![
+        ("sPeriod", "RANGE"),
+        ("sStartDate", start.as_str()),
+        ("sEndDate", end.as_str()),
+    ];
+
+    let resp = send_post(client, SRFAX_ACTION_GET_INBOX, data, srfax)?;
+
+    let obj: Inbox = resp.json()?;
+    Ok(obj)
+}
+
+/// downloads the fax in every format into every destination, returns false
+/// if the ledger says it was already downloaded
 pub fn retrieve_fax(
     client: &Client,
     srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+    item: &InboxItem,
+    direction: Direction,
+) -> Result<bool> {
+    let storage = primary(destinations)?;
+    let formats = srfax.formats();
+    let key = fax_key(srfax, item, &formats[0], &[])?;
+
+    ledger.record_seen(&srfax.name, item)?;
+    let record = ledger.get(&srfax.name, &item.FileName)?;
+    if let Some(record) = record.filter(|record| record.downloaded_at.is_some()) {
+        debug!("{} already downloaded, skipping", item.FileName);
+        if record.verified_at.is_none() {
+            verify_recorded(srfax, ledger, storage, item, &record);
+        }
+        sync_destinations(srfax, ledger, destinations, item, &record)?;
+        return Ok(false);
+    }
+
+    // files from before the ledger existed always used the srfax filename
+    // directly in `file_dir`, and were never encrypted
+    let mut adopted = None;
+    let untemplated = srfax.filename_template.is_none()
+        && srfax.dir_template.is_none()
+        && srfax.barcode_routes.is_empty()
+        && srfax.encrypt_to.is_empty();
+    if untemplated && storage.exists(&key)? {
+        // downloaded before the ledger existed, adopt the file if it checks out
+        let location = storage.location(&key);
+        match verify_stored(storage, &key, &formats[0], item) {
+            Ok(_) => {
+                debug!("{} already exists, adding to ledger", location);
+                adopted = Some(storage.get(&key)?);
+            }
+            Err(e) => {
+                warn!(
+                    "{} failed verification, downloading again. {:?}",
+                    location, e
+                );
+            }
+        }
+    }
+
+    let fetched = srfax.fetched_formats();
+    let downloaded = adopted.is_none() || fetched.len() > 1;
+    let mut files = vec![];
+    for format in fetched {
+        let file_data = match adopted.take() {
+            Some(file_data) => file_data,
+            None => download(client, srfax, item, direction.clone(), &format)?,
+        };
+        files.push((format, file_data));
+    }
+    convert_missing(client, srfax, item, &direction, &mut files)?;
+
+    let barcodes = scan_barcodes(srfax, ledger, item, &files)?;
+    let key = download_key(srfax, ledger, destinations, item, &barcodes)?;
+    store_all(srfax, ledger, destinations, item, &files, &key)?;
+
+    Ok(downloaded)
+}
+
+/// downloads the fax again even if the ledger has it, moving any existing
+/// copies aside as numbered versions, returns where it was written
+pub fn redownload_fax(
+    client: &Client,
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
     item: &InboxItem,
     direction: Direction,
+) -> Result<String> {
+    let storage = primary(destinations)?;
+    let formats = srfax.formats();
+    ledger.record_seen(&srfax.name, item)?;
+
+    let mut files = vec![];
+    for format in srfax.fetched_formats() {
+        let file_data = download(client, srfax, item, direction.clone(), &format)?;
+        files.push((format, file_data));
+    }
+    convert_missing(client, srfax, item, &direction, &mut files)?;
+    let barcodes = scan_barcodes(srfax, ledger, item, &files)?;
+
+    // replace the recorded copy, wherever it was named
+    let recorded = ledger
+        .get(&srfax.name, &item.FileName)?
+        .and_then(|record| record.local_path)
+        .and_then(|location| storage.key(&location));
+    let key = match recorded {
+        Some(key) => key,
+        None => free_key(
+            srfax,
+            ledger,
+            storage,
+            item,
+            fax_key(srfax, item, &formats[0], &barcodes)?,
+        )?,
+    };
+    storage::check_key(&key)?;
+
+    // the previous copies are only recorded as versions once the new one
+    // is stored, until then they are put back if anything fails
+    let mut kept = vec![];
+    let stored = keep_previous(destinations, &files, &key, &mut kept)
+        .and_then(|()| store_all(srfax, ledger, destinations, item, &files, &key));
+    if let Err(e) = stored {
+        for (storage, key, versioned, _) in &kept {
+            let restored = match storage.exists(key) {
+                Ok(true) => storage.remove(key),
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            }
+            .and_then(|()| storage.rename(versioned, key));
+            if let Err(e) = restored {
+                warn!(
+                    "could not put back the previous copy of {} from {}! {:?}",
+                    storage.location(key),
+                    storage.location(versioned),
+                    e
+                );
+            }
+        }
+        return Err(e);
+    }
+
+    for (storage, key, versioned, old_hash) in &kept {
+        info!(
+            "kept previous copy of {} as {}",
+            storage.location(key),
+            storage.location(versioned)
+        );
+        ledger.record_version(
+            &srfax.name,
+            &item.FileName,
+            &storage.location(versioned),
+            old_hash,
+        )?;
+    }
+    // the ocr output is of the previous copy
+    ledger.requeue_ocr(&srfax.name, &item.FileName)?;
+
+    Ok(storage.location(&key))
+}
+
+/// a previous copy of a redownloaded fax: where it was, the numbered
+/// version it was renamed to and its hash
+type Kept<'a> = (&'a dyn Storage, String, String, String);
+
+/// renames every existing copy of the fax at every destination to its next
+/// numbered version, adding them to `kept` as it goes
+fn keep_previous<'a>(
+    destinations: &'a [Destination],
+    files: &[(DownloadFormat, Vec<u8>)],
+    key: &str,
+    kept: &mut Vec<Kept<'a>>,
 ) -> Result<()> {
-    let direction = direction.to_string();
-    let download_fmt = srfax.download_fmt.to_string();
+    for destination in destinations {
+        let storage = match destination.storage() {
+            Ok(storage) => storage,
+            // reported when storing
+            Err(_) => continue,
+        };
 
+        for (format, _) in files {
+            let key = format_key(key, format);
+            if !storage.exists(&key)? {
+                continue;
+            }
+
+            let old_hash = ledger::sha256_hex(&storage.get(&key)?);
+            let versioned = version_key(storage, &key)?;
+            storage.rename(&key, &versioned)?;
+            kept.push((storage, key, versioned, old_hash));
+        }
+    }
+
+    Ok(())
+}
+
+/// where a fax that isn't downloaded yet is stored. a copy an earlier attempt
+/// left at the primary destination before failing at another one keeps its
+/// name, so the retry doesn't store it again under a new one
+fn download_key(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+    item: &InboxItem,
+    barcodes: &[Barcode],
+) -> Result<String> {
+    let storage = primary(destinations)?;
+    let format = &srfax.formats()[0];
+
+    let recorded = ledger
+        .stored_copies(&srfax.name, &item.FileName)?
+        .into_iter()
+        .find(|copy| {
+            copy.destination == destinations[0].name
+                && copy.format == format.to_string()
+                && copy.is_present()
+        })
+        .and_then(|copy| copy.location)
+        .and_then(|location| storage.key(&location));
+    if let Some(key) = recorded {
+        return Ok(key);
+    }
+
+    // templated names aren't unique, don't overwrite another fax
+    let key = fax_key(srfax, item, format, barcodes)?;
+    match srfax.filename_template {
+        Some(_) => free_key(srfax, ledger, storage, item, key),
+        None => Ok(key),
+    }
+}
+
+/// where a fax is stored, relative to the storage root. the directory comes
+/// from the first matching `barcode_routes` or `dir_template`, the name from
+/// `filename_template` or the srfax filename
+pub fn fax_key(
+    srfax: &Srfax,
+    item: &InboxItem,
+    format: &DownloadFormat,
+    barcodes: &[Barcode],
+) -> Result<String> {
     let (filename, _details_id) = split_fax_filename(&item.FileName);
     if filename.contains("..") || filename.contains('/') || filename.contains('\\') {
         return Err(SrfaxError::DirectoryTraversal(filename.to_string()));
     }
-    let filepath = {
-        let mut path = PathBuf::from(srfax.file_dir.to_owned());
 
-        if !path.exists() {
-            fs::create_dir_all(&path)?;
+    let route = barcode::route(srfax, barcodes);
+    let vars = template::Vars::new(srfax, item)
+        .with_barcode(route.map(|(_, barcode)| barcode).or(barcodes.first()));
+    let dir_template = match route {
+        Some((route, _)) => Some(&route.dir_template),
+        None => srfax.dir_template.as_ref(),
+    };
+    let mut parts = match dir_template {
+        Some(t) => template::render_dir(t, &vars)?,
+        None => vec![],
+    };
+
+    let stem = match srfax.filename_template {
+        Some(ref t) => template::render_filename(t, &vars)?,
+        None => filename.to_string(),
+    };
+    parts.push(format!("{}.{}{}", stem, format, encryption::suffix(srfax)));
+
+    let key = parts.join("/");
+    storage::check_key(&key)?;
+    Ok(key)
+}
+
+/// `key`, or `name (N).ext` if another file or another fax in the ledger
+/// already has it. a file the ledger has for this fax, like from an attempt
+/// that failed at another destination, is reused
+fn free_key(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    storage: &dyn Storage,
+    item: &InboxItem,
+    key: String,
+) -> Result<String> {
+    let (stem, ext) = split_ext(&key);
+
+    for n in 1.. {
+        let candidate = if n == 1 {
+            key.clone()
+        } else {
+            format!("{} ({}){}", stem, n, ext)
+        };
+
+        let free = match ledger.find_by_location(&srfax.name, &storage.location(&candidate))? {
+            Some(record) => record.filename == item.FileName,
+            None => !storage.exists(&candidate)?,
+        };
+        if free {
+            return Ok(candidate);
+        }
+    }
+
+    unreachable!()
+}
+
+/// the first free `name.N.ext` next to `key`
+fn version_key(storage: &dyn Storage, key: &str) -> Result<String> {
+    let (stem, ext) = split_ext(key);
+
+    for n in 1.. {
+        let candidate = format!("{}.{}{}", stem, n, ext);
+        if !storage.exists(&candidate)? {
+            return Ok(candidate);
         }
+    }
 
-        path.push(filename);
-        path.set_extension(srfax.download_fmt.to_string());
+    unreachable!()
+}
 
-        path
+/// `key` with the extension of `format`, every format of a fax shares a name
+pub fn format_key(key: &str, format: &DownloadFormat) -> String {
+    let suffix = match encryption::is_encrypted(key) {
+        true => ENCRYPTED_SUFFIX,
+        false => "",
     };
+    format!("{}.{}{}", split_ext(key).0, format, suffix)
+}
+
+/// `("dir/name", ".ext")`, the extension is empty if there isn't one. for
+/// encrypted files it's both extensions, like `.PDF.age`
+pub fn split_ext(key: &str) -> (&str, &str) {
+    let name_start = key.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let name = &key[name_start..];
+    let plain = name.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(name);
 
-    if filepath.exists() {
-        debug!("{:?} already exists, skipping", filepath);
-        return Ok(());
+    match plain.rfind('.') {
+        Some(i) if i > 0 => key.split_at(name_start + i),
+        _ => key.split_at(name_start + plain.len()),
     }
+}
+
+fn download(
+    client: &Client,
+    srfax: &Srfax,
+    item: &InboxItem,
+    direction: Direction,
+    format: &DownloadFormat,
+) -> Result<Vec<u8>> {
+    let direction = direction.to_string();
+    let download_fmt = format.to_string();
 
     let data = vec![
         ("sFaxFileName", item.FileName.as_str()),
@@ -96,13 +524,391 @@ pub fn retrieve_fax(
     let base64_data = unwrap!(result.Result).replace('\n', "");
 
     use base64::prelude::*;
-    let file_data = BASE64_STANDARD.decode(&base64_data)?;
+    Ok(BASE64_STANDARD.decode(&base64_data)?)
+}
+
+/// reads the barcodes on the fax if `barcodes` is set and records them. a
+/// failed scan doesn't hold up the download, the fax is stored without
+fn scan_barcodes(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    item: &InboxItem,
+    files: &[(DownloadFormat, Vec<u8>)],
+) -> Result<Vec<Barcode>> {
+    if !srfax.barcodes {
+        return Ok(vec![]);
+    }
+
+    let barcodes = match barcode::scan(srfax, files) {
+        Ok(barcodes) => barcodes,
+        Err(e) => {
+            warn!(
+                "error reading barcodes of {}, storing it without. {:?}",
+                item.FileName, e
+            );
+            vec![]
+        }
+    };
+    debug!("barcodes of {}: {:?}", item.FileName, barcodes);
+    ledger.record_barcodes(&srfax.name, &item.FileName, &barcodes)?;
+
+    Ok(barcodes)
+}
+
+/// adds the formats in `convert_to` that weren't downloaded, converted from
+/// the first downloaded one. falls back to downloading a format srfax's file
+/// can't be converted to
+fn convert_missing(
+    client: &Client,
+    srfax: &Srfax,
+    item: &InboxItem,
+    direction: &Direction,
+    files: &mut Vec<(DownloadFormat, Vec<u8>)>,
+) -> Result<()> {
+    for format in srfax.formats().into_iter().skip(files.len()) {
+        let (from, data) = &files[0];
+        let file_data = match convert::convert(data, from, &format) {
+            Ok(file_data) => {
+                debug!("converted {} from {} to {}", item.FileName, from, format);
+                file_data
+            }
+            Err(e) => {
+                warn!(
+                    "failed to convert {} to {}, downloading it instead. {}",
+                    item.FileName, format, e
+                );
+                download(client, srfax, item, direction.clone(), &format)?
+            }
+        };
+        files.push((format, file_data));
+    }
+    Ok(())
+}
+
+/// the destination the ledger and metadata refer to, always required
+fn primary(destinations: &[Destination]) -> Result<&dyn Storage> {
+    match destinations.first() {
+        Some(destination) => Ok(destination.storage()?),
+        None => Err(StorageError::Config("no destinations".to_string()).into()),
+    }
+}
+
+/// writes, verifies and records a downloaded fax in every format at every
+/// destination. the fax only counts as downloaded once every required
+/// destination has every format
+fn store_all(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+    item: &InboxItem,
+    files: &[(DownloadFormat, Vec<u8>)],
+    key: &str,
+) -> Result<()> {
+    let storage = primary(destinations)?;
+
+    // encrypted files can't be page counted once stored, check them first
+    let mut sealed = vec![];
+    for (format, file_data) in files {
+        if !srfax.encrypt_to.is_empty() {
+            check_pages(file_data, format, item)?;
+        }
+        sealed.push((format.clone(), encryption::seal(srfax, file_data)?));
+    }
+    let files = &sealed;
+
+    for (format, file_data) in files {
+        let key = format_key(key, format);
+        store_to(
+            srfax,
+            ledger,
+            destinations.iter(),
+            item,
+            format,
+            file_data,
+            &key,
+        )?;
+    }
+
+    let barcodes = recorded_barcodes(srfax, ledger, item)?;
+    for (format, file_data) in files {
+        let key = format_key(key, format);
+        let hash = ledger::sha256_hex(file_data);
+        let location = storage.location(&key);
+        let metadata = Metadata::new(srfax, item, format, &key, &location, &hash, &barcodes);
+        metadata::write_manifest(srfax, &metadata, item, format)?;
+    }
 
-    write_to_file(&file_data, &filepath)?;
+    let (format, file_data) = &files[0];
+    let hash = ledger::sha256_hex(file_data);
+    let location = storage.location(&format_key(key, format));
+    ledger.record_download(&srfax.name, &item.FileName, &hash, &location)?;
 
     Ok(())
 }
 
+/// copies an already downloaded fax to destinations that don't have it yet,
+/// like ones added to the config later or that were down at the time
+fn sync_destinations(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+    item: &InboxItem,
+    record: &FaxRecord,
+) -> Result<()> {
+    let storage = primary(destinations)?;
+    let copies = ledger.stored_copies(&srfax.name, &item.FileName)?;
+
+    for (i, format) in srfax.formats().iter().enumerate() {
+        let format_name = format.to_string();
+        let stored = |name: &str| {
+            copies.iter().find(|copy| {
+                copy.destination == name && copy.format == format_name && copy.stored_at.is_some()
+            })
+        };
+        let missing: Vec<&Destination> = destinations
+            .iter()
+            .filter(|d| stored(&d.name).is_none())
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        // copy from the primary destination, the ledger only has the first
+        // format for faxes stored before destinations were tracked
+        let source = match stored(&destinations[0].name) {
+            Some(copy) if copy.is_present() => (copy.location.clone(), copy.sha256.clone()),
+            Some(_) => {
+                debug!(
+                    "{} {} was archived or deleted by local retention, not copying it",
+                    item.FileName, format
+                );
+                continue;
+            }
+            None if i == 0 => (record.local_path.clone(), record.sha256.clone()),
+            None => (None, None),
+        };
+        let key = match source.0.as_deref().and_then(|l| storage.key(l)) {
+            Some(key) => key,
+            None => {
+                debug!(
+                    "{} {} is not in the primary destination, not copying it",
+                    item.FileName, format
+                );
+                continue;
+            }
+        };
+
+        if !storage.exists(&key)? {
+            warn!(
+                "{} is missing, not copying it. see `reconcile`",
+                storage.location(&key)
+            );
+            continue;
+        }
+        let file_data = storage.get(&key)?;
+        if source.1.as_deref() != Some(ledger::sha256_hex(&file_data).as_str()) {
+            warn!(
+                "{} changed since download, not copying it",
+                storage.location(&key)
+            );
+            continue;
+        }
+
+        store_to(
+            srfax,
+            ledger,
+            missing.into_iter(),
+            item,
+            format,
+            &file_data,
+            &key,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// stores the fax at each destination, recording how each went. failures at
+/// optional destinations are only warned about, the first failure at a
+/// required one is returned once every destination was tried
+fn store_to<'a>(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: impl Iterator<Item = &'a Destination>,
+    item: &InboxItem,
+    format: &DownloadFormat,
+    file_data: &[u8],
+    key: &str,
+) -> Result<()> {
+    let format_name = format.to_string();
+    let barcodes = recorded_barcodes(srfax, ledger, item)?;
+    let mut failed = None;
+
+    for destination in destinations {
+        let stored = destination
+            .storage()
+            .map_err(SrfaxError::from)
+            .and_then(|storage| store(srfax, storage, item, format, file_data, key, &barcodes));
+
+        match stored {
+            Ok(location) => {
+                let hash = ledger::sha256_hex(file_data);
+                ledger.record_stored(
+                    &srfax.name,
+                    &item.FileName,
+                    &destination.name,
+                    &format_name,
+                    &location,
+                    &hash,
+                )?;
+            }
+            Err(e) => {
+                ledger.record_store_failure(
+                    &srfax.name,
+                    &item.FileName,
+                    &destination.name,
+                    &format_name,
+                    &e.to_string(),
+                )?;
+                if destination.required {
+                    warn!("error storing {} at {}! {:?}", key, destination.name, e);
+                    failed.get_or_insert(e);
+                } else {
+                    warn!(
+                        "error storing {} at optional destination {}, continuing. {:?}",
+                        key, destination.name, e
+                    );
+                }
+            }
+        }
+    }
+
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// writes and verifies the fax and its sidecar at one destination, returns
+/// its location. an identical copy already there is kept as is
+fn store(
+    srfax: &Srfax,
+    storage: &dyn Storage,
+    item: &InboxItem,
+    format: &DownloadFormat,
+    file_data: &[u8],
+    key: &str,
+    barcodes: &[Barcode],
+) -> Result<String> {
+    let hash = ledger::sha256_hex(file_data);
+    let existing = match storage.exists(key)? {
+        true => verify_stored(storage, key, format, item).ok(),
+        false => None,
+    };
+
+    if existing.as_deref() != Some(hash.as_str()) {
+        storage.put(key, file_data)?;
+        if verify_stored(storage, key, format, item)? != hash {
+            return Err(SrfaxError::VerifyFailed(
+                item.FileName.clone(),
+                "stored file does not match the download".to_string(),
+            ));
+        }
+    }
+
+    // without its metadata the fax isn't finished, store it again later
+    let location = storage.location(key);
+    let metadata = Metadata::new(srfax, item, format, key, &location, &hash, barcodes);
+    if let Err(e) = metadata::write_sidecar(srfax, storage, key, &metadata) {
+        if existing.is_none() {
+            let _ = storage.remove(key);
+        }
+        return Err(e.into());
+    }
+
+    Ok(location)
+}
+
+/// the barcodes `scan_barcodes` recorded for the fax
+fn recorded_barcodes(srfax: &Srfax, ledger: &Ledger, item: &InboxItem) -> Result<Vec<Barcode>> {
+    Ok(ledger
+        .get(&srfax.name, &item.FileName)?
+        .map(|record| record.barcodes)
+        .unwrap_or_default())
+}
+
+/// re-reads a stored fax and checks it against the page count srfax
+/// reported, returns the hash of the file
+fn verify_stored(
+    storage: &dyn Storage,
+    key: &str,
+    format: &DownloadFormat,
+    item: &InboxItem,
+) -> Result<String> {
+    let data = storage.get(key)?;
+    check_pages(&data, format, item)?;
+
+    Ok(ledger::sha256_hex(&data))
+}
+
+/// checks a fax isn't empty and has as many pages as srfax reported, if
+/// they can be counted
+fn check_pages(data: &[u8], format: &DownloadFormat, item: &InboxItem) -> Result<()> {
+    let fail = |msg: String| SrfaxError::VerifyFailed(item.FileName.clone(), msg);
+
+    if data.is_empty() {
+        return Err(fail("file is empty".to_string()));
+    }
+
+    let expected = item.Pages.trim().parse::<usize>().ok();
+    let found = document::page_count(data, format);
+    if let (Some(expected), Some(found)) = (expected, found) {
+        if expected != found {
+            return Err(fail(format!(
+                "expected {} pages, found {}",
+                expected, found
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// verifies a fax downloaded before verification existed, so it can be
+/// deleted remotely
+fn verify_recorded(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    storage: &dyn Storage,
+    item: &InboxItem,
+    record: &FaxRecord,
+) {
+    let location = match record.local_path {
+        Some(ref location) => location,
+        None => return,
+    };
+    let key = match storage.key(location) {
+        Some(key) => key,
+        None => {
+            warn!(
+                "{} is not in the configured storage, not verifying",
+                location
+            );
+            return;
+        }
+    };
+
+    match verify_stored(storage, &key, &srfax.formats()[0], item) {
+        Ok(hash) if record.sha256.as_deref() == Some(hash.as_str()) => {
+            if let Err(e) = ledger.record_verified(&srfax.name, &item.FileName) {
+                warn!("error recording verification! {:?}", e);
+            }
+        }
+        Ok(_) => warn!("{} changed since download, not verifying", location),
+        Err(e) => warn!("{} failed verification! {:?}", location, e),
+    }
+}
+
 pub fn delete_fax(
     client: &Client,
     srfax: &Srfax,
@@ -125,14 +931,65 @@ pub fn delete_fax(
     Ok(result)
 }
 
-fn write_to_file(data: &[u8], dest: &Path) -> std::io::Result<()> {
-    let mut file = File::create(dest)?;
+/// removes temporary files left by an interrupted download and makes sure
+/// the ledger doesn't count those faxes as stored, returns how many were
+/// cleaned up
+pub fn recover_partial_downloads(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destinations: &[Destination],
+) -> Result<usize> {
+    let mut count = 0;
+
+    for (i, destination) in destinations.iter().enumerate() {
+        let storage = match destination.storage() {
+            Ok(storage) => storage,
+            Err(_) => continue,
+        };
+        // one unreachable destination mustn't keep the others from starting
+        let partials = match storage.remove_partials() {
+            Ok(partials) => partials,
+            Err(e) => {
+                warn!(
+                    "could not check {} for partial downloads! {:?}",
+                    destination.name, e
+                );
+                continue;
+            }
+        };
+        count += partials.len();
 
-    file.write_all(data)?;
-    Ok(())
+        for key in &partials {
+            match storage.exists(key) {
+                Ok(false) => (),
+                Ok(true) => continue,
+                Err(e) => {
+                    warn!("could not check {}! {:?}", storage.location(key), e);
+                    continue;
+                }
+            }
+            let location = storage.location(key);
+            // the next poll copies it to this destination again
+            let forgotten = ledger.forget_stored(&srfax.name, &destination.name, &location)?;
+            if i > 0 {
+                continue;
+            }
+
+            let filename = match ledger.find_by_local_path(&srfax.name, &location)? {
+                Some(record) => Some(record.filename),
+                None => forgotten,
+            };
+            if let Some(filename) = filename {
+                info!("re-queueing {} after interrupted download", filename);
+                ledger.requeue(&srfax.name, &filename)?;
+            }
+        }
+    }
+
+    Ok(count)
 }
 
-fn split_fax_filename(s: &str) -> (&str, &str) {
+pub fn split_fax_filename(s: &str) -> (&str, &str) {
     let index = unwrap!(s.find('|'));
 
     s.split_at(index)
@@ -152,3 +1009,112 @@ fn send_post<'a>(
 
     Ok(resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common,
+        storage::{LocalStorage, Permissions, DEFAULT_DESTINATION},
+    };
+    use std::{fs, path::Path};
+
+    fn srfax(file_dir: &Path) -> Srfax {
+        serde_json::from_value(serde_json::json!({
+            "name": "clinic",
+            "access_id": "1",
+            "access_pwd": "pwd",
+            "file_dir": file_dir,
+            "download_fmt": "PDF",
+            "delete_after": false,
+        }))
+        .unwrap()
+    }
+
+    fn item(filename: &str) -> InboxItem {
+        InboxItem {
+            FileName: filename.to_string(),
+            ReceiveStatus: "Ok".to_string(),
+            Date: "Oct 19/2026 09:15 AM".to_string(),
+            CallerID: "4165551234".to_string(),
+            RemoteID: "Dr Smith".to_string(),
+            Pages: "2".to_string(),
+            Size: "426".to_string(),
+        }
+    }
+
+    #[test]
+    fn retries_under_the_recorded_key() {
+        let dir = common::private_temp_dir("srfax-test").unwrap();
+        let (nas, mirror) = (dir.join("nas"), dir.join("mirror"));
+        let mut srfax = srfax(&nas);
+        srfax.filename_template = Some("{remote}".to_string());
+        srfax.destinations = serde_json::from_value(serde_json::json!([
+            {"name": "nas", "type": "local", "path": nas},
+            {"name": "mirror", "type": "local", "path": mirror},
+        ]))
+        .unwrap();
+        let destinations = storage::open_destinations(&srfax).unwrap();
+        let ledger = Ledger::in_memory();
+        let item = item("a|1");
+        ledger.record_seen("clinic", &item).unwrap();
+        let files = [(DownloadFormat::PDF, b"fax a".to_vec())];
+
+        // the mirror can't create its directory
+        fs::write(&mirror, b"").unwrap();
+        let key = download_key(&srfax, &ledger, &destinations, &item, &[]).unwrap();
+        assert_eq!(key, "Dr Smith.PDF");
+        assert!(store_all(&srfax, &ledger, &destinations, &item, &files, &key).is_err());
+        let record = ledger.get("clinic", "a|1").unwrap().unwrap();
+        assert!(record.downloaded_at.is_none());
+
+        fs::remove_file(&mirror).unwrap();
+        let key = download_key(&srfax, &ledger, &destinations, &item, &[]).unwrap();
+        assert_eq!(key, "Dr Smith.PDF");
+        store_all(&srfax, &ledger, &destinations, &item, &files, &key).unwrap();
+
+        for root in [&nas, &mirror] {
+            let storage = LocalStorage::new(root.to_str().unwrap(), Permissions::default());
+            assert_eq!(storage.list().unwrap(), ["Dr Smith.PDF"]);
+        }
+        let record = ledger.get("clinic", "a|1").unwrap().unwrap();
+        assert!(record.downloaded_at.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn free_key_reuses_own_copy() {
+        let dir = common::private_temp_dir("srfax-test").unwrap();
+        let srfax = srfax(&dir);
+        let storage = LocalStorage::new(dir.to_str().unwrap(), Permissions::default());
+        let ledger = Ledger::in_memory();
+        let (a, b) = (item("a|1"), item("b|2"));
+        for item in [&a, &b] {
+            ledger.record_seen("clinic", item).unwrap();
+        }
+
+        // stored at one destination by an attempt that failed at another
+        storage.put("fax.PDF", b"fax a").unwrap();
+        ledger
+            .record_stored(
+                "clinic",
+                "a|1",
+                DEFAULT_DESTINATION,
+                "PDF",
+                &storage.location("fax.PDF"),
+                &ledger::sha256_hex(b"fax a"),
+            )
+            .unwrap();
+
+        let key = |item| free_key(&srfax, &ledger, &storage, item, "fax.PDF".to_string());
+        assert_eq!(key(&a).unwrap(), "fax.PDF");
+        assert_eq!(key(&b).unwrap(), "fax (2).PDF");
+
+        // a file the ledger doesn't know isn't overwritten either
+        storage.put("fax (2).PDF", b"someone else").unwrap();
+        assert_eq!(key(&b).unwrap(), "fax (3).PDF");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,106 +1,523 @@
 use crate::{
-    config::{self, Srfax},
-    email,
+    config::{self, Srfax, CONFIG},
+    control::{self, PollTrigger},
+    disk_space, email,
+    encryption::{self, EncryptionError},
+    ledger::{Ledger, LedgerError},
+    ocr,
     response::*,
-    srfax,
+    retention::{self, RetentionError},
+    srfax::{self, SrfaxError},
+    state::{self, Health},
+    storage::{self, Destination, StorageError},
+    template::{self, TemplateError},
 };
+use chrono::NaiveDate;
 use reqwest::blocking::Client;
+use std::path::Path;
+use std::sync::mpsc;
 use std::thread;
 use std::time;
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("SrfaxService(Srfax({0:?}))")]
-    Srfax(#[from] crate::srfax::SrfaxError),
+    Srfax(#[from] SrfaxError),
+
+    #[error("SrfaxService(Config({0:?}))")]
+    Config(#[from] config::ConfigError),
+
+    #[error("SrfaxService(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("SrfaxService(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("SrfaxService(Template({0:?}))")]
+    Template(#[from] TemplateError),
+
+    #[error("SrfaxService(Encryption({0:?}))")]
+    Encryption(#[from] EncryptionError),
+
+    #[error("SrfaxService(no srfax named {0:?})")]
+    UnknownAccount(String),
+
+    #[error("SrfaxService(no matching faxes in the srfax inbox)")]
+    NotFound,
 
     #[error("SrfaxService(failed to get inbox)")]
     FailedToGetInbox,
 
-    #[error("SrfaxService(could not connect to srfax)")]
-    NoConnection,
+    #[error("SrfaxService(could not connect to srfax: {0})")]
+    NoConnection(String),
+
+    #[error("SrfaxService(authentication failed: {0})")]
+    AuthFailed(String),
+
+    #[error("SrfaxService(api error: {0})")]
+    Api(String),
+
+    #[error("SrfaxService(IO({0:?}))")]
+    Io(#[from] std::io::Error),
 }
 type Result<T> = std::result::Result<T, Error>;
 
-pub fn run_srfax_service(tick_time: time::Duration) {
-    loop {
-        thread::spawn(move || {
-            let srfaxes = config::get_srfaxes().unwrap();
+impl Error {
+    /// whether the failure came from reaching srfax rather than from the account
+    pub fn is_network(&self) -> bool {
+        match self {
+            Error::NoConnection(_) => true,
+            Error::Srfax(e) => e.is_network(),
+            _ => false,
+        }
+    }
+}
 
-            for srfax in srfaxes {
-                thread::spawn(move || match run_srfax_single(&srfax) {
-                    Ok(()) => {
-                        info!("updated srfax! name={}", srfax.name);
-                    }
-                    Err(e) => {
-                        warn!("error running srfax! {:?} {:?}", srfax, e);
+/// what happened to a single account during one poll
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub quarantined: usize,
+    pub deleted: usize,
+    pub download_errors: usize,
+    pub delete_errors: usize,
+    /// a required destination is below `free_space_min_mb`
+    pub low_space: bool,
+}
+
+/// which faxes of an account `redownload` should fetch again
+pub enum RedownloadTarget {
+    /// the srfax filename, with or without the `|details id` part
+    File(String),
+    /// every fax received between the two dates, inclusive
+    Range(NaiveDate, NaiveDate),
+}
+
+/// downloads faxes again regardless of the ledger, keeping any previous copy
+/// as a numbered version
+pub fn redownload(
+    account: &str,
+    target: &RedownloadTarget,
+) -> Result<Vec<(InboxItem, std::result::Result<String, SrfaxError>)>> {
+    let srfax = config::get_srfaxes()?
+        .into_iter()
+        .find(|srfax| srfax.name == account)
+        .ok_or_else(|| Error::UnknownAccount(account.to_string()))?;
+    let _lock = lock_downloads()?;
+    let client = Client::new();
+    let ledger = Ledger::open()?;
+    let destinations = storage::open_destinations(&srfax)?;
+
+    let inbox = match target {
+        RedownloadTarget::File(_) => srfax::get_fax_inbox(&client, &srfax)?,
+        RedownloadTarget::Range(start, end) => {
+            srfax::get_fax_inbox_range(&client, &srfax, *start, *end)?
+        }
+    };
+    if inbox.Status != ResultStatus::Success {
+        return Err(Error::FailedToGetInbox);
+    }
+
+    let items: Vec<InboxItem> = inbox
+        .Result
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|item| match target {
+            RedownloadTarget::File(name) => {
+                item.FileName == *name || srfax::split_fax_filename(&item.FileName).0 == name
+            }
+            RedownloadTarget::Range(..) => true,
+        })
+        .collect();
+    if items.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let result = srfax::redownload_fax(
+                &client,
+                &srfax,
+                &ledger,
+                &destinations,
+                &item,
+                Direction::IN,
+            );
+            match result {
+                Ok(ref location) => info!("redownloaded {} to {}", item.FileName, location),
+                Err(ref e) => warn!("error redownloading {}! {:?}", item.FileName, e),
+            }
+
+            (item, result)
+        })
+        .collect())
+}
+
+/// held while faxes may be downloaded, so no other instance mistakes this
+/// one's `.part` files for leftovers of a crash
+pub struct DownloadLock(std::fs::File);
+
+/// takes the download lock shared, next to the ledger so every instance
+/// using it sees the lock
+pub fn lock_downloads() -> Result<DownloadLock> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(format!("{}.lock", CONFIG.ledger))?;
+    file.lock_shared()?;
 
-                        email::send_email_fork(
-                            "error running",
-                            &format!("error running srfax! {:?} {:?}", srfax, e),
+    Ok(DownloadLock(file))
+}
+
+/// cleans up and re-queues the `.part` files interrupted downloads of
+/// `srfaxes` left behind, unless another instance is downloading right now.
+/// returns the download lock to hold while polling
+pub fn recover_partial_downloads(srfaxes: &[Srfax]) -> Result<DownloadLock> {
+    let lock = lock_downloads()?;
+    lock.0.unlock()?;
+    match lock.0.try_lock() {
+        Ok(()) => {
+            let recovered = recover_partials(srfaxes);
+            lock.0.unlock()?;
+            recovered?;
+        }
+        Err(std::fs::TryLockError::WouldBlock) => {
+            info!("another instance is downloading, leaving partial downloads alone")
+        }
+        Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+    lock.0.lock_shared()?;
+
+    Ok(lock)
+}
+
+fn recover_partials(srfaxes: &[Srfax]) -> Result<()> {
+    let ledger = Ledger::open()?;
+
+    for srfax in srfaxes {
+        let destinations = match storage::open_destinations(srfax) {
+            Ok(destinations) => destinations,
+            Err(e) => {
+                warn!(
+                    "could not check {} for partial downloads! {:?}",
+                    srfax.name, e
+                );
+                continue;
+            }
+        };
+        let count = srfax::recover_partial_downloads(srfax, &ledger, &destinations)?;
+        if count > 0 {
+            warn!("cleaned up {} partial downloads for {}", count, srfax.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// warns about local directories that allow more than `dir_mode`, they
+/// were made before it was set or changed by hand
+pub fn check_permissions() -> Result<()> {
+    for srfax in config::get_srfaxes()? {
+        let dir_mode = match storage::Permissions::new(&srfax) {
+            Ok(permissions) => match permissions.dir_mode {
+                Some(dir_mode) => dir_mode,
+                None => continue,
+            },
+            Err(e) => {
+                warn!("invalid permissions for {}! {:?}", srfax.name, e);
+                continue;
+            }
+        };
+
+        let mut roots: Vec<String> = storage::destination_configs(&srfax)
+            .into_iter()
+            .filter_map(|destination| match destination.storage {
+                storage::StorageConfig::Local { path } => Some(path),
+                _ => None,
+            })
+            .collect();
+        roots.extend(srfax.manifest_dir.clone());
+
+        for root in roots {
+            match storage::permissive_dirs(Path::new(&root), dir_mode) {
+                Ok(dirs) => {
+                    for (dir, mode) in dirs {
+                        warn!(
+                            "{:?} of {} has mode {:04o}, more permissive than dir_mode {:04o}",
+                            dir, srfax.name, mode, dir_mode
                         );
                     }
-                });
+                }
+                Err(e) => warn!("could not check permissions of {}! {:?}", root, e),
             }
-        });
+        }
+    }
+
+    Ok(())
+}
 
-        thread::sleep(tick_time);
+pub fn run_srfax_service(tick_time: time::Duration) {
+    let (trigger, polls) = mpsc::channel::<PollTrigger>();
+    if let Err(e) = control::start_server(trigger.clone()) {
+        warn!("could not start control socket! {:?}", e);
     }
+    ocr::start_worker(tick_time);
+
+    let mut next_tick = time::Instant::now();
+    loop {
+        let now = time::Instant::now();
+        let wait = next_tick.saturating_duration_since(now);
+        if wait.is_zero() {
+            poll_accounts(None);
+            next_tick = now + tick_time;
+            continue;
+        }
+
+        // sleep until the next tick unless a poll is triggered first
+        match polls.recv_timeout(wait) {
+            Ok(account) => {
+                info!("poll triggered! account={:?}", account);
+                poll_accounts(account);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(wait),
+        }
+    }
+}
+
+fn poll_accounts(only: PollTrigger) {
+    thread::spawn(move || {
+        let srfaxes = config::get_srfaxes().unwrap();
+
+        for srfax in srfaxes {
+            if only.as_ref().is_some_and(|name| *name != srfax.name) {
+                continue;
+            }
+            let poll = match state::begin_poll(&srfax.name) {
+                Some(poll) => poll,
+                None => {
+                    debug!(
+                        "srfax paused or still polling, skipping. name={}",
+                        srfax.name
+                    );
+                    continue;
+                }
+            };
+
+            thread::spawn(move || match run_srfax_single(&srfax) {
+                Ok(report) => {
+                    info!("updated srfax! name={} report={:?}", srfax.name, report);
+                    poll.finish(format!("{:?}", report));
+                }
+                Err(e) => {
+                    warn!("error running srfax! {:?} {:?}", srfax, e);
+                    poll.finish(e.to_string());
+
+                    email::send_email_fork(
+                        "error running",
+                        &format!("error running srfax! {:?} {:?}", srfax, e),
+                    );
+                }
+            });
+        }
+    });
 }
 
-fn run_srfax_single(srfax: &Srfax) -> Result<()> {
+/// polls each account once, in parallel, and waits for all of them to finish
+pub fn run_srfax_once(srfaxes: Vec<Srfax>) -> Vec<(String, Result<Report>)> {
+    let handles: Vec<_> = srfaxes
+        .into_iter()
+        .map(|srfax| {
+            thread::spawn(move || {
+                let result = run_srfax_single(&srfax);
+                if let Err(ref e) = result {
+                    warn!("error running srfax! {:?} {:?}", srfax, e);
+                }
+
+                (srfax.name, result)
+            })
+        })
+        .collect();
+
+    handles.into_iter().map(|h| unwrap!(h.join())).collect()
+}
+
+fn run_srfax_single(srfax: &Srfax) -> Result<Report> {
     let client = Client::new();
+    let ledger = Ledger::open()?;
+    let mut report = Report::default();
 
-    if !srfax::test_connection(&client) {
-        return Err(Error::NoConnection);
+    // a bad template or key is a config problem, not a problem with any one
+    // fax
+    if let Some(ref t) = srfax.filename_template {
+        template::validate(t)?;
+    }
+    if let Some(ref t) = srfax.dir_template {
+        template::validate_dir(t)?;
+    }
+    for route in &srfax.barcode_routes {
+        template::validate_dir(&route.dir_template)?;
+    }
+    encryption::recipients(srfax)?;
+
+    match srfax::check_health(&client, srfax) {
+        Health::Healthy => (),
+        Health::Unknown => return Err(Error::Api("health unknown".to_string())),
+        Health::NetworkError(e) => return Err(Error::NoConnection(e)),
+        Health::AuthFailed(e) => return Err(Error::AuthFailed(e)),
+        Health::ApiError(e) => return Err(Error::Api(e)),
     }
 
-    let result = srfax::get_fax_inbox(&client, srfax)?;
+    let result = match srfax::get_fax_inbox(&client, srfax) {
+        Ok(result) => result,
+        Err(e) => {
+            if e.is_network() {
+                state::set_health(&srfax.name, Health::NetworkError(e.to_string()));
+            }
+            return Err(e.into());
+        }
+    };
 
     if result.Status != ResultStatus::Success {
         return Err(Error::FailedToGetInbox);
     }
+    // a working inbox call proves the credentials, so skip the next probe
+    state::set_health(&srfax.name, Health::Healthy);
 
-    if result.Result.is_none() {
-        return Ok(());
-    }
-    let inbox = result.Result.unwrap();
+    let inbox = result.Result.unwrap_or_default();
+    let destinations = storage::open_destinations(srfax)?;
+
+    // nothing is downloaded or deleted while a required destination is
+    // full, local retention still runs as it frees space
+    report.low_space = disk_space::check(srfax, &destinations);
+    let inbox: &[InboxItem] = if report.low_space {
+        debug!("{} is low on space, skipping the inbox", srfax.name);
+        &[]
+    } else {
+        &inbox
+    };
 
     for item in inbox {
         debug!("srfax item: {:?}", item);
 
-        match srfax::retrieve_fax(&client, srfax, &item, Direction::IN) {
-            Ok(()) => (),
+        let record = ledger.get(&srfax.name, &item.FileName)?;
+        if record.is_some_and(|record| record.quarantined_at.is_some()) {
+            debug!("{} is quarantined, skipping", item.FileName);
+            report.quarantined += 1;
+            continue;
+        }
+
+        match srfax::retrieve_fax(&client, srfax, &ledger, &destinations, item, Direction::IN) {
+            Ok(true) => report.downloaded += 1,
+            Ok(false) => report.skipped += 1,
             Err(e) => {
+                report.download_errors += 1;
                 warn!("error retrieving fax! item={:?} error={:?}", item, e);
                 email::send_email_fork(
                     "error retrieving fax",
                     &format!("error retrieving fax! item={:?} error={:?}", item, e),
                 );
-                continue;
+
+                // an outage says nothing about the fax itself
+                if !e.is_outage() {
+                    record_failure(srfax, &ledger, item, &e)?;
+                }
             }
         }
+    }
 
-        if srfax.delete_after {
-            match srfax::delete_fax(&client, srfax, &item, Direction::IN) {
-                Ok(resp) => {
-                    if resp.Status != ResultStatus::Success {
-                        warn!("error deleting fax! msg={}", resp.Result);
-                    }
+    if srfax.delete_after || srfax.remote_retention_days.is_some() {
+        delete_verified(&client, srfax, &ledger, inbox, &mut report)?;
+    }
+    if (srfax.archive_after_days.is_some() || srfax.local_retention_days.is_some())
+        && state::begin_local_retention(&srfax.name)
+    {
+        apply_local_retention(srfax, &ledger, &destinations);
+    }
+
+    Ok(report)
+}
+
+/// a destination failing local retention is retried on the next run, it
+/// doesn't fail the poll
+fn apply_local_retention(srfax: &Srfax, ledger: &Ledger, destinations: &[Destination]) {
+    for destination in destinations {
+        let result = destination
+            .storage()
+            .map_err(RetentionError::from)
+            .and_then(|storage| retention::apply_local(srfax, ledger, &destination.name, storage));
+
+        match result {
+            Ok(report) if report.archived > 0 || report.deleted > 0 => info!(
+                "local retention done! name={} destination={} archived={} deleted={}",
+                srfax.name, destination.name, report.archived, report.deleted
+            ),
+            Ok(_) => (),
+            Err(e) => warn!(
+                "error applying local retention! name={} destination={} error={:?}",
+                srfax.name, destination.name, e
+            ),
+        }
+    }
+}
+
+fn record_failure(srfax: &Srfax, ledger: &Ledger, item: &InboxItem, e: &SrfaxError) -> Result<()> {
+    let quarantine_after = config::CONFIG.quarantine_after;
+
+    if ledger.record_failure(&srfax.name, item, &e.to_string(), quarantine_after)? {
+        warn!(
+            "quarantined fax after {} failures! name={} FileName=[{}]",
+            quarantine_after, srfax.name, item.FileName
+        );
+        email::send_email_fork(
+            "fax quarantined",
+            &format!(
+                "fax failed {} times and will no longer be retried until released with \
+                `quarantine retry`. name={} item={:?} error={:?}",
+                quarantine_after, srfax.name, item, e
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// deletes the faxes that `retention` says are due, which are always
+/// verified on disk first
+pub fn delete_verified(
+    client: &Client,
+    srfax: &Srfax,
+    ledger: &Ledger,
+    inbox: &[InboxItem],
+    report: &mut Report,
+) -> Result<()> {
+    for item in retention::due_for_remote_delete(srfax, ledger, inbox)? {
+        match srfax::delete_fax(client, srfax, item, Direction::IN) {
+            Ok(resp) => {
+                if resp.Status == ResultStatus::Success {
+                    report.deleted += 1;
+                    ledger.record_deleted(&srfax.name, &item.FileName)?;
+                } else {
+                    report.delete_errors += 1;
+                    warn!("error deleting fax! msg={}", resp.Result);
                 }
-                Err(e) => {
-                    warn!(
+            }
+            Err(e) => {
+                report.delete_errors += 1;
+                warn!(
+                    "error deleting fax! FileName=[{}] RemoteID=[{}] error={:?}",
+                    item.FileName, item.RemoteID, e
+                );
+                email::send_email_fork(
+                    "error deleting fax",
+                    &format!(
                         "error deleting fax! FileName=[{}] RemoteID=[{}] error={:?}",
                         item.FileName, item.RemoteID, e
-                    );
-                    email::send_email_fork(
-                        "error deleting fax",
-                        &format!(
-                            "error deleting fax! FileName=[{}] RemoteID=[{}] error={:?}",
-                            item.FileName, item.RemoteID, e
-                        ),
-                    );
-                    continue;
-                }
+                    ),
+                );
             }
         }
     }
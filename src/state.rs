@@ -0,0 +1,148 @@
+use crate::disk_space::SpaceLevel;
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// result of the last credential probe against the srfax api
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Health {
+    Unknown,
+    Healthy,
+    NetworkError(String),
+    AuthFailed(String),
+    ApiError(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccountState {
+    pub health: Health,
+    pub checked: Option<DateTime<Local>>,
+    pub paused: bool,
+    pub polling: bool,
+    pub last_poll: Option<DateTime<Local>>,
+    pub last_result: Option<String>,
+    pub last_local_retention: Option<DateTime<Local>>,
+    /// free space of each destination with thresholds, by name
+    pub space: HashMap<String, SpaceLevel>,
+}
+impl Default for AccountState {
+    fn default() -> Self {
+        AccountState {
+            health: Health::Unknown,
+            checked: None,
+            paused: false,
+            polling: false,
+            last_poll: None,
+            last_result: None,
+            last_local_retention: None,
+            space: HashMap::new(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref STATE: Mutex<HashMap<String, AccountState>> = Mutex::new(HashMap::new());
+}
+
+pub fn get(name: &str) -> AccountState {
+    let state = unwrap!(STATE.lock());
+
+    state.get(name).cloned().unwrap_or_default()
+}
+
+pub fn set_health(name: &str, health: Health) {
+    let mut state = unwrap!(STATE.lock());
+    let account = state.entry(name.to_string()).or_default();
+
+    if account.health != health {
+        info!("srfax health changed! name={} health={:?}", name, health);
+    }
+    account.health = health;
+    account.checked = Some(Local::now());
+}
+
+pub fn set_paused(name: &str, paused: bool) {
+    let mut state = unwrap!(STATE.lock());
+    let account = state.entry(name.to_string()).or_default();
+
+    info!("srfax paused={} name={}", paused, name);
+    account.paused = paused;
+}
+
+/// an account being polled, the poll ends when it is dropped, even if the
+/// polling thread panics
+pub struct PollGuard {
+    name: String,
+    result: Option<String>,
+}
+impl PollGuard {
+    pub fn finish(mut self, result: String) {
+        self.result = Some(result);
+    }
+}
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        let result = self
+            .result
+            .take()
+            .unwrap_or_else(|| "poll panicked".to_string());
+        end_poll(&self.name, result);
+    }
+}
+
+/// marks the account as being polled, returns None if it is paused or a
+/// poll is already running
+pub fn begin_poll(name: &str) -> Option<PollGuard> {
+    let mut state = unwrap!(STATE.lock());
+    let account = state.entry(name.to_string()).or_default();
+
+    if account.paused || account.polling {
+        return None;
+    }
+    account.polling = true;
+
+    Some(PollGuard {
+        name: name.to_string(),
+        result: None,
+    })
+}
+
+fn end_poll(name: &str, result: String) {
+    // a panic elsewhere while holding the lock mustn't keep the poll running
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let account = state.entry(name.to_string()).or_default();
+
+    account.polling = false;
+    account.last_poll = Some(Local::now());
+    account.last_result = Some(result);
+}
+
+/// local retention only runs once an hour, returns whether it is due and
+/// marks it as run if so
+pub fn begin_local_retention(name: &str) -> bool {
+    let mut state = unwrap!(STATE.lock());
+    let account = state.entry(name.to_string()).or_default();
+
+    let now = Local::now();
+    if account
+        .last_local_retention
+        .is_some_and(|last| now - last < chrono::Duration::hours(1))
+    {
+        return false;
+    }
+    account.last_local_retention = Some(now);
+
+    true
+}
+
+/// records how much space a destination has left, returns the level it had
+/// before
+pub fn set_space_level(name: &str, destination: &str, level: SpaceLevel) -> SpaceLevel {
+    let mut state = unwrap!(STATE.lock());
+    let account = state.entry(name.to_string()).or_default();
+
+    account
+        .space
+        .insert(destination.to_string(), level)
+        .unwrap_or(SpaceLevel::Ok)
+}
@@ -0,0 +1,157 @@
+use super::{is_hidden, partial_dest, Permissions, Result, Storage, PARTIAL_SUFFIX};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// a directory on this machine or a mounted share
+pub struct LocalStorage {
+    root: PathBuf,
+    permissions: Permissions,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, permissions: Permissions) -> LocalStorage {
+        LocalStorage {
+            root: PathBuf::from(root),
+            permissions,
+        }
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        path.extend(key.split('/'));
+        path
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let dest = self.path(key);
+        if let Some(dir) = dest.parent() {
+            self.permissions.create_dir_all(dir)?;
+        }
+
+        write_to_file(data, &dest, &self.permissions)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(key))?)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.path(key).exists())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let to = self.path(to);
+        fs::rename(self.path(from), &to)?;
+        sync_dir(&to)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        fs::remove_file(self.path(key))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        list_keys(&self.root, "", &mut keys)?;
+        keys.retain(|key| !is_hidden(key));
+        Ok(keys)
+    }
+
+    fn location(&self, key: &str) -> String {
+        self.path(key).to_string_lossy().to_string()
+    }
+
+    fn key(&self, location: &str) -> Option<String> {
+        let rel = Path::new(location).strip_prefix(&self.root).ok()?;
+        let parts: Vec<_> = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+
+        Some(parts.join("/"))
+    }
+
+    fn free_space(&self) -> Result<Option<u64>> {
+        // the root is only created with the first fax
+        let mut dir = self.root.as_path();
+        while !dir.is_dir() {
+            dir = match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+        }
+
+        Ok(Some(fs4::available_space(dir)?))
+    }
+
+    fn remove_partials(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        list_keys(&self.root, "", &mut keys)?;
+
+        let mut dests = vec![];
+        for key in keys {
+            if let Some(dest) = partial_dest(&key) {
+                warn!("removing partial download {:?}", self.path(&key));
+                fs::remove_file(self.path(&key))?;
+                dests.push(dest);
+            }
+        }
+
+        Ok(dests)
+    }
+}
+
+fn list_keys(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> std::io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let key = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+
+        if entry.path().is_dir() {
+            list_keys(&entry.path(), &format!("{}/", key), keys)?;
+        } else {
+            keys.push(key);
+        }
+    }
+
+    Ok(())
+}
+
+/// writes to a hidden temporary file next to `dest` and renames it into
+/// place, so a crash never leaves a truncated file at `dest`
+pub fn write_to_file(data: &[u8], dest: &Path, permissions: &Permissions) -> std::io::Result<()> {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dest.with_file_name(format!(".{}{}", name, PARTIAL_SUFFIX));
+
+    let mut file = File::create(&tmp)?;
+    permissions.apply_file(&file)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, dest)?;
+    sync_dir(dest)?;
+    Ok(())
+}
+
+/// the rename is only durable once the directory entry is synced
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,416 @@
+mod local;
+mod permissions;
+mod s3;
+mod sftp;
+mod webdav;
+
+pub use local::{write_to_file, LocalStorage};
+pub use permissions::{permissive_dirs, Permissions};
+pub use s3::{S3Config, S3Storage};
+pub use sftp::{SftpConfig, SftpStorage};
+pub use webdav::{WebdavConfig, WebdavStorage};
+
+use crate::config::Srfax;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Storage(IO({0:?}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("Storage(Reqwest({0:?}))")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("Storage(Ssh({0:?}))")]
+    Ssh(#[from] ssh2::Error),
+
+    #[error("Storage({0} {1} returned {2})")]
+    Http(String, String, reqwest::StatusCode),
+
+    #[error("Storage(host key for {0} not trusted: {1})")]
+    HostKey(String, String),
+
+    #[error("Storage(invalid key {0:?})")]
+    InvalidKey(String),
+
+    #[error("Storage(destination {0} unavailable: {1})")]
+    Unavailable(String, String),
+
+    #[error("Storage(bad config: {0})")]
+    Config(String),
+}
+type Result<T> = std::result::Result<T, StorageError>;
+
+/// where an account's faxes are written, see `Storage`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    Local { path: String },
+    S3(S3Config),
+    Sftp(SftpConfig),
+    Webdav(WebdavConfig),
+}
+
+/// somewhere faxes can be written to. keys are relative paths separated by
+/// `/` that have already been checked by `check_key`
+pub trait Storage {
+    /// writes `data` to `key`, creating parent directories as needed. a
+    /// failed or interrupted write never leaves a partial file at `key`
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    fn exists(&self, key: &str) -> Result<bool>;
+
+    fn rename(&self, from: &str, to: &str) -> Result<()>;
+
+    fn remove(&self, key: &str) -> Result<()>;
+
+    /// every stored key, without temporary files
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// how `key` is recorded in the ledger and shown to people
+    fn location(&self, key: &str) -> String;
+
+    /// the key a `location` refers to, if it's in this storage
+    fn key(&self, location: &str) -> Option<String>;
+
+    /// bytes that can still be written, `None` if the storage can't tell
+    fn free_space(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// removes temporary files left by an interrupted write, returns the
+    /// keys they were meant for
+    fn remove_partials(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+/// the storage an account is configured with, `file_dir` by default
+pub fn config(srfax: &Srfax) -> StorageConfig {
+    srfax
+        .storage
+        .clone()
+        .unwrap_or_else(|| StorageConfig::Local {
+            path: srfax.file_dir.clone(),
+        })
+}
+
+/// one of the places an account's faxes are mirrored to
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub name: String,
+    /// faxes are only deleted from srfax once every required destination
+    /// has them, failures at optional ones are only warned about
+    #[serde(default = "default_required")]
+    pub required: bool,
+    /// override the account's `free_space_warn_mb`
+    #[serde(default)]
+    pub free_space_warn_mb: Option<u64>,
+    /// override the account's `free_space_min_mb`
+    #[serde(default)]
+    pub free_space_min_mb: Option<u64>,
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+}
+fn default_required() -> bool {
+    true
+}
+
+/// the destination used when an account has no `destinations`
+pub const DEFAULT_DESTINATION: &str = "default";
+
+/// every destination of an account, just `storage` unless `destinations`
+/// is set
+pub fn destination_configs(srfax: &Srfax) -> Vec<DestinationConfig> {
+    if !srfax.destinations.is_empty() {
+        return srfax.destinations.clone();
+    }
+
+    vec![DestinationConfig {
+        name: DEFAULT_DESTINATION.to_string(),
+        required: true,
+        free_space_warn_mb: None,
+        free_space_min_mb: None,
+        storage: config(srfax),
+    }]
+}
+
+/// names of the destinations a fax has to be stored at before it can be
+/// deleted from srfax
+pub fn required_destinations(srfax: &Srfax) -> Vec<String> {
+    destination_configs(srfax)
+        .into_iter()
+        .filter(|d| d.required)
+        .map(|d| d.name)
+        .collect()
+}
+
+/// an opened destination. optional ones that couldn't be opened are kept
+/// so every fax can warn about them
+pub struct Destination {
+    pub name: String,
+    pub required: bool,
+    pub free_space_warn_mb: Option<u64>,
+    pub free_space_min_mb: Option<u64>,
+    storage: std::result::Result<Box<dyn Storage>, String>,
+}
+
+impl Destination {
+    pub fn storage(&self) -> Result<&dyn Storage> {
+        match self.storage {
+            Ok(ref storage) => Ok(storage.as_ref()),
+            Err(ref e) => Err(StorageError::Unavailable(self.name.clone(), e.clone())),
+        }
+    }
+}
+
+/// opens every destination of an account, failing only when a required one
+/// can't be opened. the first one is the primary, its location is what the
+/// ledger and metadata refer to
+pub fn open_destinations(srfax: &Srfax) -> Result<Vec<Destination>> {
+    let configs = destination_configs(srfax);
+    if !configs.first().map(|d| d.required).unwrap_or(false) {
+        return Err(StorageError::Config(format!(
+            "the first destination of {} has to be required",
+            srfax.name
+        )));
+    }
+
+    let permissions = Permissions::new(srfax)?;
+    let mut names = vec![];
+    let mut destinations = vec![];
+    for config in configs {
+        if names.contains(&config.name) {
+            return Err(StorageError::Config(format!(
+                "destination {} of {} is listed twice",
+                config.name, srfax.name
+            )));
+        }
+        names.push(config.name.clone());
+
+        let storage = match open(&config.storage, &permissions) {
+            Ok(storage) => Ok(storage),
+            Err(e) if config.required => return Err(e),
+            Err(e) => {
+                warn!(
+                    "could not open optional destination {} of {}! {}",
+                    config.name, srfax.name, e
+                );
+                Err(e.to_string())
+            }
+        };
+
+        destinations.push(Destination {
+            name: config.name,
+            required: config.required,
+            free_space_warn_mb: config.free_space_warn_mb.or(srfax.free_space_warn_mb),
+            free_space_min_mb: config.free_space_min_mb.or(srfax.free_space_min_mb),
+            storage,
+        });
+    }
+
+    Ok(destinations)
+}
+
+/// `permissions` only apply to local and sftp storage
+pub fn open(config: &StorageConfig, permissions: &Permissions) -> Result<Box<dyn Storage>> {
+    let storage: Box<dyn Storage> = match config {
+        StorageConfig::Local { path } => Box::new(LocalStorage::new(path, permissions.clone())),
+        StorageConfig::S3(config) => Box::new(S3Storage::new(config)?),
+        StorageConfig::Sftp(config) => Box::new(SftpStorage::connect(config, permissions.clone())?),
+        StorageConfig::Webdav(config) => Box::new(WebdavStorage::new(config)?),
+    };
+
+    Ok(storage)
+}
+
+/// rejects keys that are absolute or could leave the storage root
+pub fn check_key(key: &str) -> Result<()> {
+    let bad = key.is_empty()
+        || key.starts_with('/')
+        || key.contains('\\')
+        || key.contains(':')
+        || key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..");
+
+    if bad {
+        return Err(StorageError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+/// the hidden name a key is written to before being moved into place
+pub fn partial_key(key: &str) -> String {
+    match key.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/.{}{}", dir, name, PARTIAL_SUFFIX),
+        None => format!(".{}{}", key, PARTIAL_SUFFIX),
+    }
+}
+
+/// the key a partial write was meant for, if `key` is one
+pub fn partial_dest(key: &str) -> Option<String> {
+    let (dir, name) = match key.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), key),
+    };
+    let name = name.strip_prefix('.')?.strip_suffix(PARTIAL_SUFFIX)?;
+
+    Some(format!("{}{}", dir, name))
+}
+
+pub const PARTIAL_SUFFIX: &str = ".part";
+
+/// whether the last part of a key is hidden, like a partial write
+fn is_hidden(key: &str) -> bool {
+    key.rsplit('/').next().unwrap_or_default().starts_with('.')
+}
+
+/// percent-encodes everything but unreserved characters, and `/` if asked
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if keep_slash => out.push('/'),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn uri_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// the text of every element named `name`, ignoring namespace prefixes.
+/// just enough xml for s3 and webdav listings
+fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = match rest.find(['>', ' ', '\t', '\r', '\n', '/']) {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        let local = tag.rsplit(':').next().unwrap_or_default();
+        if local != name || tag.starts_with(['/', '?', '!']) {
+            continue;
+        }
+
+        let open_end = match rest.find('>') {
+            Some(i) => i,
+            None => break,
+        };
+        // `<name/>` has no text
+        if rest[..open_end].ends_with('/') {
+            found.push("");
+            rest = &rest[open_end + 1..];
+            continue;
+        }
+        let body = &rest[open_end + 1..];
+        let close = format!("</{}>", tag);
+        match body.find(&close) {
+            Some(i) => {
+                found.push(&body[..i]);
+                rest = &body[i + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    found
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_round_trip() {
+        let key = "2026/10/Dr Smith & co/fax (2)+ü.pdf";
+        let encoded = uri_encode(key, true);
+        assert_eq!(
+            encoded,
+            "2026/10/Dr%20Smith%20%26%20co/fax%20%282%29%2B%C3%BC.pdf"
+        );
+        assert_eq!(uri_encode(key, false).matches("%2F").count(), 3);
+        assert_eq!(uri_decode(&encoded), key);
+        // not a valid escape, kept as it is
+        assert_eq!(uri_decode("100%!%4"), "100%!%4");
+    }
+
+    #[test]
+    fn finds_xml_elements() {
+        let xml = r#"<?xml version="1.0"?>
+            <D:multistatus xmlns:D="DAV:">
+              <D:response><D:href>/dav/a%20b.pdf</D:href><D:resourcetype/></D:response>
+              <D:response>
+                <D:href>/dav/x/</D:href>
+                <D:resourcetype><D:collection/></D:resourcetype>
+              </D:response>
+            </D:multistatus>"#;
+
+        assert_eq!(xml_elements(xml, "href"), ["/dav/a%20b.pdf", "/dav/x/"]);
+        assert_eq!(xml_elements(xml, "resourcetype"), ["", "<D:collection/>"]);
+        assert_eq!(xml_elements(xml, "response").len(), 2);
+        assert!(xml_elements(xml, "missing").is_empty());
+
+        let s3 = "<ListBucketResult><Contents><Key>a &amp; b.pdf</Key></Contents>\
+            <IsTruncated>false</IsTruncated></ListBucketResult>";
+        let keys: Vec<String> = xml_elements(s3, "Key")
+            .into_iter()
+            .map(xml_unescape)
+            .collect();
+        assert_eq!(keys, ["a & b.pdf"]);
+        assert_eq!(xml_elements(s3, "IsTruncated"), ["false"]);
+    }
+
+    #[test]
+    fn partial_keys() {
+        assert_eq!(partial_key("2026/10/fax.pdf"), "2026/10/.fax.pdf.part");
+        assert_eq!(partial_key("fax.pdf"), ".fax.pdf.part");
+        assert_eq!(
+            partial_dest("2026/10/.fax.pdf.part").as_deref(),
+            Some("2026/10/fax.pdf")
+        );
+        assert_eq!(partial_dest(".fax.pdf.part").as_deref(), Some("fax.pdf"));
+        assert_eq!(partial_dest("2026/10/fax.pdf.part"), None);
+        assert_eq!(partial_dest("2026/.10/fax.pdf"), None);
+
+        assert!(is_hidden("2026/10/.fax.pdf.part"));
+        assert!(!is_hidden("2026/.10/fax.pdf"));
+    }
+}
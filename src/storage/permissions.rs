@@ -0,0 +1,149 @@
+use super::{Result, StorageError};
+use crate::config::Srfax;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// the mode and group faxes, sidecars and the directories created for them
+/// get, instead of whatever the umask gives them. unix only
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Permissions {
+    pub fn new(srfax: &Srfax) -> Result<Permissions> {
+        let permissions = Permissions {
+            file_mode: srfax.file_mode.as_deref().map(parse_mode).transpose()?,
+            dir_mode: srfax.dir_mode.as_deref().map(parse_mode).transpose()?,
+            gid: srfax.group.as_deref().map(lookup_group).transpose()?,
+        };
+
+        if cfg!(not(unix)) && srfax.group.is_some() {
+            warn!("group is ignored on this platform");
+        }
+        Ok(permissions)
+    }
+
+    /// sets the mode and group of a file that was just created, before it's
+    /// renamed into place
+    pub fn apply_file(&self, file: &File) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // changing the group can clear the setgid bit, so it goes first
+            if let Some(gid) = self.gid {
+                std::os::unix::fs::fchown(file, None, Some(gid))?;
+            }
+            if let Some(mode) = self.file_mode {
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = file;
+
+        Ok(())
+    }
+
+    pub fn apply_dir(&self, dir: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if let Some(gid) = self.gid {
+                std::os::unix::fs::chown(dir, None, Some(gid))?;
+            }
+            if let Some(mode) = self.dir_mode {
+                fs::set_permissions(dir, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = dir;
+
+        Ok(())
+    }
+
+    /// like `fs::create_dir_all`, but every directory it creates gets
+    /// `dir_mode` and the group
+    pub fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        if dir.as_os_str().is_empty() || dir.is_dir() {
+            return Ok(());
+        }
+        if let Some(parent) = dir.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        match fs::create_dir(dir) {
+            Ok(()) => self.apply_dir(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// an octal mode like `"0640"`, json has no octal numbers
+fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(parsed) if parsed <= 0o7777 && !digits.is_empty() => Ok(parsed),
+        _ => Err(StorageError::Config(format!("invalid mode {:?}", mode))),
+    }
+}
+
+/// a group name or numeric gid
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    match nix::unistd::Group::from_name(group) {
+        Ok(Some(found)) => Ok(found.gid.as_raw()),
+        Ok(None) => Err(StorageError::Config(format!("unknown group {:?}", group))),
+        Err(e) => Err(StorageError::Config(format!(
+            "could not look up group {:?}: {}",
+            group, e
+        ))),
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_group: &str) -> Result<u32> {
+    Ok(0)
+}
+
+/// directories under `root`, including it, that allow more than `dir_mode`
+pub fn permissive_dirs(root: &Path, dir_mode: u32) -> io::Result<Vec<(PathBuf, u32)>> {
+    let mut found = vec![];
+    if root.is_dir() {
+        walk_dirs(root, dir_mode, &mut found)?;
+    }
+    Ok(found)
+}
+
+#[cfg(unix)]
+fn walk_dirs(dir: &Path, dir_mode: u32, found: &mut Vec<(PathBuf, u32)>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(dir)?.permissions().mode() & 0o7777;
+    if mode & !dir_mode & 0o777 != 0 {
+        found.push((dir.to_path_buf(), mode));
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            walk_dirs(&entry.path(), dir_mode, found)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn walk_dirs(_dir: &Path, _dir_mode: u32, _found: &mut Vec<(PathBuf, u32)>) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use super::{is_hidden, partial_dest, partial_key, Permissions, Result, Storage, StorageError};
+use ssh2::{
+    CheckResult, FileStat, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp,
+};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+/// a directory on an ssh server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// a private key file, tried before `password`
+    #[serde(default)]
+    pub private_key: Option<String>,
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    /// an openssh `known_hosts` file the server's key has to be in
+    pub known_hosts: String,
+    /// the directory faxes are written under
+    pub path: String,
+}
+fn default_port() -> u16 {
+    22
+}
+
+pub struct SftpStorage {
+    config: SftpConfig,
+    /// only the modes, a local group means nothing on the server
+    permissions: Permissions,
+    // the session has to outlive the sftp channel
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl SftpStorage {
+    pub fn connect(config: &SftpConfig, permissions: Permissions) -> Result<SftpStorage> {
+        let tcp = TcpStream::connect((config.host.as_str(), config.port))?;
+        let mut session = Session::new()?;
+        session.set_timeout(60_000);
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+
+        check_host_key(&session, config)?;
+
+        if let Some(ref key) = config.private_key {
+            session.userauth_pubkey_file(
+                &config.username,
+                None,
+                Path::new(key),
+                config.passphrase.as_deref(),
+            )?;
+        } else if let Some(ref password) = config.password {
+            session.userauth_password(&config.username, password)?;
+        } else {
+            return Err(StorageError::Config(format!(
+                "sftp {}: set private_key or password",
+                config.host
+            )));
+        }
+
+        let sftp = session.sftp()?;
+        Ok(SftpStorage {
+            config: config.clone(),
+            permissions,
+            _session: session,
+            sftp,
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut path = PathBuf::from(&self.config.path);
+        path.extend(key.split('/'));
+        path
+    }
+
+    fn create_dirs(&self, key: &str) -> Result<()> {
+        let mut dir = PathBuf::from(&self.config.path);
+        let parts: Vec<&str> = key.split('/').collect();
+
+        for part in &parts[..parts.len() - 1] {
+            dir.push(part);
+            if self.sftp.stat(&dir).is_err() {
+                let mode = self.permissions.dir_mode.unwrap_or(0o755);
+                self.sftp.mkdir(&dir, mode as i32)?;
+                // the server's umask applies to mkdir
+                if self.permissions.dir_mode.is_some() {
+                    self.sftp.setstat(&dir, mode_stat(mode))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_keys(&self, dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<()> {
+        for (path, stat) in self.sftp.readdir(dir)? {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let key = format!("{}{}", prefix, name);
+
+            if stat.is_dir() {
+                self.list_keys(&path, &format!("{}/", key), keys)?;
+            } else {
+                keys.push(key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn mode_stat(mode: u32) -> FileStat {
+    FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: Some(mode),
+        atime: None,
+        mtime: None,
+    }
+}
+
+fn check_host_key(session: &Session, config: &SftpConfig) -> Result<()> {
+    let host = format!("{}:{}", config.host, config.port);
+    let (key, _kind) = session
+        .host_key()
+        .ok_or_else(|| StorageError::HostKey(host.clone(), "no host key".to_string()))?;
+
+    let mut known = session.known_hosts()?;
+    known.read_file(Path::new(&config.known_hosts), KnownHostFileKind::OpenSSH)?;
+
+    match known.check_port(&config.host, config.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(StorageError::HostKey(host, "host key changed".to_string())),
+        CheckResult::NotFound => Err(StorageError::HostKey(
+            host,
+            format!("not in {}", config.known_hosts),
+        )),
+        CheckResult::Failure => Err(StorageError::HostKey(
+            host,
+            "error checking known_hosts".to_string(),
+        )),
+    }
+}
+
+impl Storage for SftpStorage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.create_dirs(key)?;
+
+        let tmp = self.path(&partial_key(key));
+        let mode = self.permissions.file_mode.unwrap_or(0o644);
+        let mut file = self.sftp.open_mode(
+            &tmp,
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            mode as i32,
+            OpenType::File,
+        )?;
+        if self.permissions.file_mode.is_some() {
+            file.setstat(mode_stat(mode))?;
+        }
+        file.write_all(data)?;
+        file.fsync().or_else(|e| match e.code() {
+            // not every server has the fsync extension
+            ssh2::ErrorCode::SFTP(8) => Ok(()),
+            _ => Err(e),
+        })?;
+        drop(file);
+
+        self.sftp.rename(
+            &tmp,
+            &self.path(key),
+            Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE),
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        self.sftp.open(self.path(key))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool> {
+        match self.sftp.stat(&self.path(key)) {
+            Ok(_) => Ok(true),
+            // LIBSSH2_FX_NO_SUCH_FILE
+            Err(e) if e.code() == ssh2::ErrorCode::SFTP(2) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.sftp.rename(&self.path(from), &self.path(to), None)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.sftp.unlink(&self.path(key))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        self.list_keys(Path::new(&self.config.path), "", &mut keys)?;
+        keys.retain(|key| !is_hidden(key));
+        Ok(keys)
+    }
+
+    fn location(&self, key: &str) -> String {
+        format!(
+            "sftp://{}@{}:{}{}",
+            self.config.username,
+            self.config.host,
+            self.config.port,
+            self.path(key).to_string_lossy()
+        )
+    }
+
+    fn key(&self, location: &str) -> Option<String> {
+        let root = self.location("");
+        let key = location.strip_prefix(root.trim_end_matches('/'))?;
+        Some(key.trim_start_matches('/').to_string())
+    }
+
+    fn remove_partials(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        self.list_keys(Path::new(&self.config.path), "", &mut keys)?;
+
+        let mut dests = vec![];
+        for key in keys {
+            if let Some(dest) = partial_dest(&key) {
+                warn!("removing partial upload {}", self.location(&key));
+                self.sftp.unlink(&self.path(&key))?;
+                dests.push(dest);
+            }
+        }
+
+        Ok(dests)
+    }
+}
@@ -0,0 +1,221 @@
+use super::{
+    is_hidden, partial_dest, partial_key, uri_decode, uri_encode, xml_elements, xml_unescape,
+    Result, Storage, StorageError,
+};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode, Url};
+
+/// a collection on a webdav server, like nextcloud or a nas share
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebdavConfig {
+    /// the collection faxes are written under, e.g.
+    /// `https://cloud.example.com/remote.php/dav/files/fax/inbox/`
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+pub struct WebdavStorage {
+    config: WebdavConfig,
+    base: Url,
+    client: Client,
+}
+
+impl WebdavStorage {
+    pub fn new(config: &WebdavConfig) -> Result<WebdavStorage> {
+        let mut base = Url::parse(&config.url)
+            .map_err(|e| StorageError::Config(format!("webdav url: {}", e)))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Ok(WebdavStorage {
+            config: config.clone(),
+            base,
+            client: Client::new(),
+        })
+    }
+
+    fn url(&self, key: &str) -> Url {
+        let mut url = self.base.clone();
+        url.set_path(&format!("{}{}", self.base.path(), uri_encode(key, true)));
+        url
+    }
+
+    fn request(&self, method: &str, key: &str) -> RequestBuilder {
+        let method = unwrap!(Method::from_bytes(method.as_bytes()).ok());
+        let req = self.client.request(method, self.url(key));
+
+        match self.config.username {
+            Some(ref user) => req.basic_auth(user, self.config.password.as_deref()),
+            None => req,
+        }
+    }
+
+    fn check(&self, resp: Response, what: &str, key: &str) -> Result<Response> {
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(StorageError::Http(
+                what.to_string(),
+                self.location(key),
+                status,
+            ));
+        }
+        Ok(resp)
+    }
+
+    fn create_dirs(&self, key: &str) -> Result<()> {
+        let parts: Vec<&str> = key.split('/').collect();
+        let mut dir = String::new();
+
+        for part in &parts[..parts.len() - 1] {
+            dir.push_str(part);
+            dir.push('/');
+
+            let resp = self.request("MKCOL", &dir).send()?;
+            // 405 means it already exists
+            if resp.status() != StatusCode::METHOD_NOT_ALLOWED {
+                self.check(resp, "MKCOL", &dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn move_to(&self, from: &str, to: &str) -> Result<()> {
+        let resp = self
+            .request("MOVE", from)
+            .header("Destination", self.url(to).as_str())
+            .header("Overwrite", "T")
+            .send()?;
+        self.check(resp, "MOVE", from)?;
+        Ok(())
+    }
+
+    fn list_keys(&self, dir: &str, keys: &mut Vec<String>) -> Result<()> {
+        let resp = self
+            .request("PROPFIND", dir)
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(r#"<?xml version="1.0"?><propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#)
+            .send()?;
+        let body = self.check(resp, "PROPFIND", dir)?.text()?;
+
+        let dir_path = uri_decode(self.url(dir).path());
+        for response in xml_elements(&body, "response") {
+            let href = match xml_elements(response, "href").first() {
+                Some(href) => uri_decode(&xml_unescape(href)),
+                None => continue,
+            };
+            // hrefs can be full urls or just paths
+            let path = match Url::parse(&href) {
+                Ok(url) => uri_decode(url.path()),
+                Err(_) => href,
+            };
+            let name = match path.strip_prefix(&dir_path) {
+                Some(name) if !name.trim_end_matches('/').is_empty() => {
+                    name.trim_end_matches('/').to_string()
+                }
+                _ => continue,
+            };
+            let key = format!("{}{}", dir, name);
+
+            if !xml_elements(response, "collection").is_empty() {
+                self.list_keys(&format!("{}/", key), keys)?;
+            } else {
+                keys.push(key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for WebdavStorage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.create_dirs(key)?;
+
+        // a server may keep a half written file when the connection drops
+        let tmp = partial_key(key);
+        let resp = self.request("PUT", &tmp).body(data.to_vec()).send()?;
+        self.check(resp, "PUT", &tmp)?;
+        self.move_to(&tmp, key)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let resp = self.request("GET", key).send()?;
+        Ok(self.check(resp, "GET", key)?.bytes()?.to_vec())
+    }
+
+    fn exists(&self, key: &str) -> Result<bool> {
+        let resp = self.request("HEAD", key).send()?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        self.check(resp, "HEAD", key)?;
+        Ok(true)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.move_to(from, to)
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let resp = self.request("DELETE", key).send()?;
+        self.check(resp, "DELETE", key)?;
+        Ok(())
+    }
+
+    fn free_space(&self) -> Result<Option<u64>> {
+        let resp = self
+            .request("PROPFIND", "")
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(r#"<?xml version="1.0"?><propfind xmlns="DAV:"><prop><quota-available-bytes/></prop></propfind>"#)
+            .send()?;
+        let body = self.check(resp, "PROPFIND", "")?.text()?;
+
+        // servers without quotas leave it out or send it empty
+        Ok(xml_elements(&body, "quota-available-bytes")
+            .first()
+            .and_then(|bytes| bytes.trim().parse().ok()))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        self.list_keys("", &mut keys)?;
+        keys.retain(|key| !is_hidden(key));
+        Ok(keys)
+    }
+
+    fn location(&self, key: &str) -> String {
+        self.url(key).to_string()
+    }
+
+    fn key(&self, location: &str) -> Option<String> {
+        let url = Url::parse(location).ok()?;
+        if url.origin() != self.base.origin() {
+            return None;
+        }
+        let key = url.path().strip_prefix(self.base.path())?;
+        Some(uri_decode(key))
+    }
+
+    fn remove_partials(&self) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        self.list_keys("", &mut keys)?;
+
+        let mut dests = vec![];
+        for key in keys {
+            if let Some(dest) = partial_dest(&key) {
+                warn!("removing partial upload {}", self.location(&key));
+                self.remove(&key)?;
+                dests.push(dest);
+            }
+        }
+
+        Ok(dests)
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{barcode::Barcode, config::Srfax, response::InboxItem, srfax};
+use chrono::{Local, NaiveDateTime};
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("Template(unknown placeholder {{{0}}})")]
+    UnknownPlaceholder(String),
+
+    #[error("Template(unclosed placeholder in {0:?})")]
+    Unclosed(String),
+}
+type Result<T> = std::result::Result<T, TemplateError>;
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "filename",
+    "details_id",
+    "account",
+    "caller",
+    "remote",
+    "phonebook",
+    "pages",
+    "date",
+    "time",
+    "yyyy",
+    "mm",
+    "dd",
+    "HH",
+    "MM",
+    "SS",
+    "barcode",
+];
+
+/// the storage root, `file_dir` by default, only allowed at the start of a
+/// directory template
+const FILE_DIR: &str = "{file_dir}";
+
+/// the values a template can refer to for a single fax
+pub struct Vars<'a> {
+    srfax: &'a Srfax,
+    item: &'a InboxItem,
+    received: NaiveDateTime,
+    barcode: Option<&'a Barcode>,
+}
+
+impl<'a> Vars<'a> {
+    pub fn new(srfax: &'a Srfax, item: &'a InboxItem) -> Vars<'a> {
+        Vars {
+            srfax,
+            item,
+            // faxes without a parseable date are named after when they arrived here
+            received: item
+                .received()
+                .unwrap_or_else(|| Local::now().naive_local()),
+            barcode: None,
+        }
+    }
+
+    /// the barcode `{barcode}` is the value of
+    pub fn with_barcode(mut self, barcode: Option<&'a Barcode>) -> Vars<'a> {
+        self.barcode = barcode;
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let (filename, details_id) = srfax::split_fax_filename(&self.item.FileName);
+        let caller = self.item.CallerID.trim();
+
+        let value = match name {
+            "filename" => filename.to_string(),
+            "details_id" => details_id.trim_start_matches('|').to_string(),
+            "account" => self.srfax.name.clone(),
+            "caller" => caller.to_string(),
+            "remote" => self.item.RemoteID.trim().to_string(),
+            "phonebook" => self
+                .srfax
+                .phonebook
+                .get(caller)
+                .cloned()
+                .unwrap_or_else(|| caller.to_string()),
+            "pages" => self.item.Pages.trim().to_string(),
+            "date" => self.received.format("%Y-%m-%d").to_string(),
+            "time" => self.received.format("%H%M%S").to_string(),
+            "yyyy" => self.received.format("%Y").to_string(),
+            "mm" => self.received.format("%m").to_string(),
+            "dd" => self.received.format("%d").to_string(),
+            "HH" => self.received.format("%H").to_string(),
+            "MM" => self.received.format("%M").to_string(),
+            "SS" => self.received.format("%S").to_string(),
+            "barcode" => self
+                .barcode
+                .map(|barcode| barcode.data.clone())
+                .unwrap_or_default(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+/// checks a template only uses known placeholders
+pub fn validate(template: &str) -> Result<()> {
+    for_each_placeholder(template, |name| {
+        if PLACEHOLDERS.contains(&name) {
+            Ok(String::new())
+        } else {
+            Err(TemplateError::UnknownPlaceholder(name.to_string()))
+        }
+    })?;
+
+    Ok(())
+}
+
+/// renders a template into a single safe path component
+pub fn render_filename(template: &str, vars: &Vars) -> Result<String> {
+    let rendered = for_each_placeholder(template, |name| {
+        vars.get(name)
+            .map(|value| sanitize(&value))
+            .ok_or_else(|| TemplateError::UnknownPlaceholder(name.to_string()))
+    })?;
+
+    Ok(sanitize(&rendered))
+}
+
+/// checks a directory template, `{file_dir}` may only be the first component
+pub fn validate_dir(template: &str) -> Result<()> {
+    for (i, component) in dir_components(template).enumerate() {
+        if component == FILE_DIR && i == 0 {
+            continue;
+        }
+        validate(component)?;
+    }
+
+    Ok(())
+}
+
+/// renders a directory template into path components relative to the
+/// storage root, every component is sanitized on its own so a value can't
+/// add or escape directories. a leading `{file_dir}` is the root itself
+pub fn render_dir(template: &str, vars: &Vars) -> Result<Vec<String>> {
+    let mut parts = vec![];
+
+    for (i, component) in dir_components(template).enumerate() {
+        if component == FILE_DIR && i == 0 {
+            continue;
+        }
+        parts.push(render_filename(component, vars)?);
+    }
+
+    Ok(parts)
+}
+
+fn dir_components(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+}
+
+/// replaces every `{name}` with `f(name)`, `{{` and `}}` are literal braces
+fn for_each_placeholder<F>(template: &str, mut f: F) -> Result<String>
+where
+    F: FnMut(&str) -> Result<String>,
+{
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    return Err(TemplateError::Unclosed(template.to_string()));
+                }
+                out.push_str(&f(&name)?);
+            }
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// makes a string safe to use as a file or directory name on both windows
+/// and unix
+pub fn sanitize(s: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    const MAX_LEN: usize = 180;
+
+    let mut out: String = s
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // leading dots would hide the file or walk up a directory, windows
+    // drops trailing dots and spaces
+    out = out
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    if out.len() > MAX_LEN {
+        let mut end = MAX_LEN;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+        out = out.trim_end_matches(['.', ' ']).to_string();
+    }
+
+    let stem = out.split('.').next().unwrap_or_default().to_uppercase();
+    if RESERVED.contains(&stem.as_str()) {
+        out.insert(0, '_');
+    }
+    if out.is_empty() {
+        out.push('_');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn srfax() -> Srfax {
+        serde_json::from_str(
+            r#"{
+                "name": "clinic",
+                "access_id": "1",
+                "access_pwd": "",
+                "file_dir": "faxes",
+                "download_fmt": "PDF",
+                "delete_after": false,
+                "phonebook": {"4165551234": "../Dr Smith/"}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn item(caller: &str, remote: &str) -> InboxItem {
+        InboxItem {
+            FileName: "20261019120000-1234-5_1|98765".to_string(),
+            ReceiveStatus: "Ok".to_string(),
+            Date: "Oct 19/2026 09:15 AM".to_string(),
+            CallerID: caller.to_string(),
+            RemoteID: remote.to_string(),
+            Pages: "2".to_string(),
+            Size: "426".to_string(),
+        }
+    }
+
+    #[test]
+    fn sanitizes_separators_and_dots() {
+        assert_eq!(sanitize("a/b\\c"), "a_b_c");
+        assert_eq!(sanitize("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize(".hidden"), "hidden");
+        assert_eq!(sanitize("name. . "), "name");
+        assert_eq!(sanitize("a<b>c:d\"e|f?g*h\ni"), "a_b_c_d_e_f_g_h_i");
+        assert_eq!(sanitize(""), "_");
+    }
+
+    #[test]
+    fn sanitizes_reserved_names() {
+        assert_eq!(sanitize("CON"), "_CON");
+        assert_eq!(sanitize("nul.pdf"), "_nul.pdf");
+        assert_eq!(sanitize("com1.tar.gz"), "_com1.tar.gz");
+        assert_eq!(sanitize("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize("LPT10"), "LPT10");
+    }
+
+    #[test]
+    fn truncates_long_names() {
+        let long = "é".repeat(100);
+        let out = sanitize(&long);
+        assert_eq!(out.len(), 180);
+        assert!(out.chars().all(|c| c == 'é'));
+
+        let out = sanitize(&format!("{}. x", "a".repeat(179)));
+        assert_eq!(out, "a".repeat(179));
+    }
+
+    #[test]
+    fn renders_filename() {
+        let srfax = srfax();
+        let item = item("4165551234", "Dr/Smith");
+        let vars = Vars::new(&srfax, &item);
+
+        assert_eq!(
+            render_filename("{date}_{time}_{phonebook}_{pages}p {{x}}", &vars).unwrap(),
+            "2026-10-19_091500__Dr Smith__2p {x}"
+        );
+        assert_eq!(
+            render_filename("{remote}-{details_id}", &vars).unwrap(),
+            "Dr_Smith-98765"
+        );
+        assert_eq!(
+            render_filename("{caller}/..", &vars).unwrap(),
+            "4165551234_"
+        );
+        assert!(matches!(
+            render_filename("{nope}", &vars),
+            Err(TemplateError::UnknownPlaceholder(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            render_filename("{date", &vars),
+            Err(TemplateError::Unclosed(_))
+        ));
+    }
+
+    #[test]
+    fn renders_dir() {
+        let srfax = srfax();
+        let item = item("4165551234", "..");
+        let vars = Vars::new(&srfax, &item);
+
+        assert_eq!(
+            render_dir("{file_dir}/{yyyy}/{mm}//{phonebook}", &vars).unwrap(),
+            ["2026", "10", "_Dr Smith_"]
+        );
+        assert_eq!(
+            render_dir("in\\{remote}/{caller}", &vars).unwrap(),
+            ["in", "_", "4165551234"]
+        );
+        assert_eq!(
+            render_dir("x/{file_dir}", &vars).unwrap_err().to_string(),
+            "Template(unknown placeholder {file_dir})"
+        );
+
+        assert!(validate_dir("{file_dir}/{yyyy}").is_ok());
+        assert!(validate_dir("{yyyy}/{file_dir}").is_err());
+        assert!(validate_dir("{account}/{barcode}").is_ok());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    config::{self, ConfigError, Srfax},
+    ledger::{self, Ledger, LedgerError},
+    metadata,
+    storage::{self, Storage, StorageError},
+};
+use clap::{Arg, ArgMatches, Command};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("Verify(Config({0:?}))")]
+    Config(#[from] ConfigError),
+
+    #[error("Verify(Ledger({0:?}))")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Verify(Storage({0:?}))")]
+    Storage(#[from] StorageError),
+
+    #[error("Verify(no srfax named {0:?})")]
+    UnknownAccount(String),
+}
+type Result<T> = std::result::Result<T, VerifyError>;
+
+/// differences between one destination and the checksums in the ledger,
+/// files are named by their location
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// files that still match their checksum
+    pub ok: usize,
+    /// hashed differently than when they were stored
+    pub modified: Vec<String>,
+    /// in the ledger but not stored anymore
+    pub missing: Vec<String>,
+    /// stored but not in the ledger, other than metadata files
+    pub unexpected: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_clean(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// re-hashes every file of a destination and compares it with the ledger,
+/// previous copies kept by `redownload` and archives included
+pub fn verify(
+    srfax: &Srfax,
+    ledger: &Ledger,
+    destination: &str,
+    storage: &dyn Storage,
+) -> Result<Verification> {
+    let mut result = Verification::default();
+
+    let mut expected: HashMap<String, String> = HashMap::new();
+    for (_filename, copy) in ledger.copies_at(&srfax.name, destination)? {
+        let (location, sha256) = match (copy.location, copy.sha256) {
+            (Some(location), Some(sha256)) => (location, sha256),
+            _ => continue,
+        };
+        match storage.key(&location) {
+            Some(key) => {
+                expected.insert(key, sha256);
+            }
+            // the destination was moved since
+            None => result.missing.push(location),
+        }
+    }
+    for (location, sha256) in ledger.archives(&srfax.name, destination)? {
+        match storage.key(&location) {
+            Some(key) => {
+                expected.insert(key, sha256);
+            }
+            None => result.missing.push(location),
+        }
+    }
+    for (location, sha256) in ledger.versions(&srfax.name)? {
+        // versions of every destination are in one list
+        if let Some(key) = storage.key(&location) {
+            expected.insert(key, sha256);
+        }
+    }
+
+    let mut stored = storage.list()?;
+    stored.retain(|key| !metadata::is_sidecar(srfax, key));
+    stored.sort();
+
+    for key in &stored {
+        let location = storage.location(key);
+        match expected.get(key) {
+            Some(sha256) => {
+                if ledger::sha256_hex(&storage.get(key)?) == *sha256 {
+                    result.ok += 1;
+                } else {
+                    result.modified.push(location);
+                }
+            }
+            None => result.unexpected.push(location),
+        }
+    }
+
+    let stored: HashSet<&String> = stored.iter().collect();
+    for key in expected.keys() {
+        if !stored.contains(key) {
+            result.missing.push(storage.location(key));
+        }
+    }
+    result.missing.sort();
+
+    Ok(result)
+}
+
+pub fn add_to_clap(app: Command) -> Command {
+    app.subcommand(
+        Command::new("verify")
+            .about("re-hash downloaded faxes and compare them with the ledger")
+            .arg(Arg::new("account").long("account")),
+    )
+}
+
+pub fn check_clap(matches: &ArgMatches) -> Result<bool> {
+    let matches = match matches.subcommand_matches("verify") {
+        Some(matches) => matches,
+        None => return Ok(false),
+    };
+    let account = matches.get_one::<String>("account");
+
+    let srfaxes: Vec<Srfax> = config::get_srfaxes()?
+        .into_iter()
+        .filter(|srfax| account.is_none_or(|name| *name == srfax.name))
+        .collect();
+    if let (Some(name), true) = (account, srfaxes.is_empty()) {
+        return Err(VerifyError::UnknownAccount(name.clone()));
+    }
+
+    let ledger = Ledger::open()?;
+    let mut clean = true;
+
+    for srfax in &srfaxes {
+        for destination in storage::open_destinations(srfax)? {
+            let result = destination
+                .storage()
+                .map_err(VerifyError::from)
+                .and_then(|storage| verify(srfax, &ledger, &destination.name, storage));
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    println!(
+                        "{} ({}): could not verify: {}",
+                        srfax.name, destination.name, e
+                    );
+                    clean = false;
+                    continue;
+                }
+            };
+
+            println!(
+                "{} ({}): {} ok, {} modified, {} missing, {} unexpected",
+                srfax.name,
+                destination.name,
+                result.ok,
+                result.modified.len(),
+                result.missing.len(),
+                result.unexpected.len()
+            );
+            for location in &result.modified {
+                println!("  modified: {}", location);
+            }
+            for location in &result.missing {
+                println!("  missing: {}", location);
+            }
+            for location in &result.unexpected {
+                println!("  unexpected: {}", location);
+            }
+            clean &= result.is_clean();
+        }
+    }
+
+    if !clean {
+        eprintln!("done, exiting..");
+        std::process::exit(crate::EXIT_CODE_ERROR);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common, response::InboxItem, storage::LocalStorage};
+
+    #[test]
+    fn verifies_local_destination() {
+        let dir = common::private_temp_dir("srfax-test").unwrap();
+        let srfax: Srfax = serde_json::from_value(serde_json::json!({
+            "name": "clinic",
+            "access_id": "1",
+            "access_pwd": "pwd",
+            "file_dir": dir,
+            "download_fmt": "PDF",
+            "delete_after": false,
+        }))
+        .unwrap();
+        let storage = LocalStorage::new(dir.to_str().unwrap(), Default::default());
+        let ledger = Ledger::in_memory();
+
+        let stored = [
+            ("a|1", "a.PDF", b"fax a".as_slice()),
+            ("b|2", "b.PDF", b"fax b".as_slice()),
+            ("c|3", "c.PDF", b"fax c".as_slice()),
+        ];
+        for (filename, key, data) in stored {
+            ledger
+                .record_seen(
+                    "clinic",
+                    &InboxItem {
+                        FileName: filename.to_string(),
+                        ReceiveStatus: "Ok".to_string(),
+                        Date: "Oct 19/2026 09:15 AM".to_string(),
+                        CallerID: "4165551234".to_string(),
+                        RemoteID: "Dr Smith".to_string(),
+                        Pages: "2".to_string(),
+                        Size: "426".to_string(),
+                    },
+                )
+                .unwrap();
+            storage.put(key, data).unwrap();
+            ledger
+                .record_stored(
+                    "clinic",
+                    filename,
+                    "default",
+                    "PDF",
+                    &storage.location(key),
+                    &ledger::sha256_hex(data),
+                )
+                .unwrap();
+        }
+
+        let result = verify(&srfax, &ledger, "default", &storage).unwrap();
+        assert_eq!(result.ok, 3);
+        assert!(result.is_clean());
+
+        storage.put("b.PDF", b"changed").unwrap();
+        storage.remove("c.PDF").unwrap();
+        storage.put("d.PDF", b"fax d").unwrap();
+        let result = verify(&srfax, &ledger, "default", &storage).unwrap();
+        assert_eq!(result.ok, 1);
+        assert_eq!(result.modified, [storage.location("b.PDF")]);
+        assert_eq!(result.missing, [storage.location("c.PDF")]);
+        assert_eq!(result.unexpected, [storage.location("d.PDF")]);
+        assert!(!result.is_clean());
+
+        // another destination's copies aren't expected here
+        let result = verify(&srfax, &ledger, "mirror", &storage).unwrap();
+        assert_eq!(result.ok, 0);
+        assert_eq!(result.unexpected.len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}